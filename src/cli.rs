@@ -1,15 +1,26 @@
 use crate::Result;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 
 use clap::{self, Parser, Subcommand};
-use gyat::root;
+use gyat::{
+    hash::Oid,
+    objects::{self, CommitObject, FType, ObjType},
+    repo::Repository,
+    utils,
+};
 
+mod blame;
 mod create;
 mod observe;
+mod patch;
 mod track;
 mod fallback;
+mod graph;
+mod reflog;
+mod reset;
+mod stats;
+mod status;
+mod tag;
 
 /// Watered down VCS
 #[derive(Parser)]
@@ -17,68 +28,204 @@ mod fallback;
 pub struct Cli {
     #[command(subcommand)]
     command: Command,
+    /// When to color the output.
+    #[arg(long, global = true, value_enum, default_value_t = utils::ColorChoice::Auto)]
+    color: utils::ColorChoice,
 }
 
 impl Cli {
     /// Runs the program.
     pub fn run(&self) -> Result<()> {
         match &self.command {
-            Command::Create { name } => Ok(create::create(name)?),
-            Command::Observe { paths } => Ok(observe::observe(paths)?),
-            Command::Track { message, track_all } => Ok(track::track(message, *track_all)?),
-            Command::Fallback { commit_hash } => Ok(fallback::fallback(commit_hash.as_ref())?),
-            Command::Wood { lines } => Ok(Self::wood(*lines)?),
+            Command::Create { name, force } => Ok(create::create(name, *force)?),
+            // clap already refuses --patch along with --stdin or --dry-run.
+            Command::Observe {
+                paths,
+                patch: true,
+                ..
+            } => Ok(patch::patch(paths, utils::Painter::new(self.color))?),
+            Command::Observe {
+                paths,
+                stdin,
+                nul,
+                dry_run,
+                quiet,
+                patch: false,
+            } => Ok(observe::observe(paths, *stdin, *nul, *dry_run, *quiet)?),
+            Command::Track {
+                message,
+                message_file,
+                track_all,
+                amend,
+                allow_empty,
+                patch,
+            } => {
+                if *patch {
+                    // the whole repository, like `-a`, wherever it's run from.
+                    let repo = Repository::open()?;
+                    patch::patch(
+                        std::slice::from_ref(&repo.paths().repo_root),
+                        utils::Painter::new(self.color),
+                    )?;
+                }
+                Ok(track::track(
+                    message,
+                    message_file,
+                    *track_all,
+                    *amend,
+                    *allow_empty,
+                )?)
+            }
+            Command::Fallback {
+                commit_hash,
+                paths,
+                force,
+            } => Ok(fallback::fallback(commit_hash.as_ref(), paths, *force)?),
+            Command::Wood {
+                start,
+                lines,
+                oneline,
+                reverse,
+                verify,
+                graph,
+            } => Ok(Self::wood(
+                start, *lines, *oneline, *reverse, *verify, *graph,
+            )?),
+            Command::Reflog => Ok(reflog::reflog()?),
+            Command::Reset { commit, hard } => Ok(reset::reset(commit, *hard)?),
+            Command::Blame { path } => Ok(blame::blame(path)?),
+            Command::Status { short } => {
+                Ok(status::status(*short, utils::Painter::new(self.color))?)
+            }
+            Command::Tag { name, message } => Ok(tag::tag(name, message)?),
+            Command::Stats => Ok(stats::stats()?),
+            Command::ListObjects {
+                blobs,
+                trees,
+                commits: _,
+            } => {
+                // clap makes sure exactly one of them is set.
+                let kind = match (blobs, trees) {
+                    (true, _) => ObjType::File(FType::Blob),
+                    (_, true) => ObjType::File(FType::Tree),
+                    _ => ObjType::Commit,
+                };
+                for hash in objects::list_objects(&Repository::open()?, kind)? {
+                    println!("{hash}");
+                }
+                Ok(())
+            }
         }
     }
 
     /// Prints out a log of commit hashes, for now.
     ///
-    /// * `lines`:
-    fn wood(lines: usize) -> Result<()> {
+    /// * `start`: the commit (or tag, or anything `objects::resolve_rev` takes) to start from.
+    ///   HEAD if None.
+    /// * `lines`: the maximum number of commits to print.
+    /// * `oneline`: print the abbreviated hash and the first line of the message instead.
+    /// * `reverse`: print the oldest commit first. Still only the latest `lines` commits.
+    /// * `verify`: check that each commit's tree and blobs exist, and print what's missing next to
+    ///   the commit. The walk goes on either way.
+    /// * `graph`: draw the history as a graph instead, see `graph::print_graph`. Without `start`,
+    ///   every branch is in it, not just HEAD.
+    fn wood(
+        start: &Option<String>,
+        lines: usize,
+        oneline: bool,
+        reverse: bool,
+        verify: bool,
+        graph: bool,
+    ) -> Result<()> {
         if lines == 0 {
             return Ok(());
         }
 
-        let repo_root = root::get_repo_root(std::env::current_dir()?.as_path())
-            .ok_or("Current directory in not in gyat repositroy")?;
-        let gyat_path = repo_root.join(".gyat");
-
-        if !gyat_path.exists() {
-            return Err("Repository is not created".into());
-        }
+        let repo = Repository::open()?;
+        let start_given = start.is_some();
+        let start = match start {
+            Some(start) => objects::resolve_rev(&repo, start)?,
+            None => match repo.head_commit()? {
+                Some(head) => head,
+                // nothing tracked yet.
+                None => return Ok(()),
+            },
+        };
 
-        let mut curr_commit = {
-            let mut reader = BufReader::new(File::open(gyat_path.join("HEAD"))?);
-            let mut buf = String::new();
-            reader.read_line(&mut buf)?;
-            buf
+        // what's printed for each commit.
+        let describe = |commit: &Oid, read: Result<CommitObject>| -> String {
+            let broken = match (verify, &read) {
+                (true, Ok(c)) => objects::verify_tree(&c.root).err(),
+                (true, Err(e)) => Some(e.to_string().into()),
+                (false, _) => None,
+            }
+            .map(|e| format!("\tbroken: {e}"))
+            .unwrap_or_default();
+            if oneline {
+                let message = read.map(|c| c.message).unwrap_or_default();
+                format!(
+                    "{} {}{broken}",
+                    &commit.to_string()[..7],
+                    message.lines().next().unwrap_or_default()
+                )
+            } else {
+                format!("{commit}{broken}")
+            }
         };
-        println!("{}", curr_commit.trim());
 
-        let commits_path = gyat_path.join("commits");
-        for _ in 1..lines {
-            let curr_commit_file =
-                File::open(commits_path.join(Path::new(&curr_commit))).map_err(|e| {
-                    format!(
-                        "{}: {e}",
-                        &commits_path.join(Path::new(&curr_commit)).display()
-                    )
-                })?;
-            let mut reader = BufReader::new(curr_commit_file);
-            curr_commit.clear();
-            reader.read_line(&mut curr_commit)?;
-            curr_commit = curr_commit.split(':').nth(1).unwrap().trim().to_owned();
-            if curr_commit.is_empty() {
-                return Ok(());
+        if graph {
+            let mut starts = vec![start];
+            if !start_given {
+                starts.extend(Self::branch_heads(&repo)?);
             }
-            if curr_commit.len() < 20 {
-                return Ok(());
+            return graph::print_graph(&repo, &starts, lines, |hash, commit| {
+                describe(hash, Ok(commit))
+            });
+        }
+
+        // collected first, so they can be printed in either order.
+        let mut commits = vec![start];
+        while commits.len() < lines {
+            let curr_commit = commits[commits.len() - 1];
+            match repo.read_commit(&curr_commit) {
+                Ok(CommitObject {
+                    parent: Some(parent),
+                    ..
+                }) => commits.push(parent),
+                Ok(_) => break,
+                Err(e) => {
+                    // print whatever history we could read instead of nothing.
+                    eprintln!("Stopped at commit {curr_commit}: {e}");
+                    break;
+                }
             }
-            println!("{}", curr_commit.trim());
+        }
+
+        if reverse {
+            commits.reverse();
+        }
+        for commit in commits {
+            println!("{}", describe(&commit, repo.read_commit(&commit)));
         }
 
         Ok(())
     }
+
+    /// The commit of every branch that has one, sorted by branch name.
+    ///
+    /// * `repo`:
+    fn branch_heads(repo: &Repository) -> Result<Vec<Oid>> {
+        let mut branches = Vec::new();
+        for entry in std::fs::read_dir(repo.paths().refs_path.join("heads"))? {
+            let entry = entry?;
+            // a branch without a commit yet is an empty file.
+            if let Ok(hash) = std::fs::read_to_string(entry.path())?.trim().parse() {
+                branches.push((entry.file_name(), hash));
+            }
+        }
+        branches.sort();
+        Ok(branches.into_iter().map(|(_, hash)| hash).collect())
+    }
 }
 
 #[derive(Subcommand)]
@@ -89,6 +236,9 @@ enum Command {
         /// The name of the repository (hence the directory name). If this option is not supplied,
         /// create the repository in the current directory instead.
         name: Option<String>,
+        /// Create the repository even if it ends up inside another gyat repository.
+        #[arg(long)]
+        force: bool,
     },
     /// Take a look at the repository for changes.
     /// Use . to track all files in the current working directory.
@@ -96,30 +246,134 @@ enum Command {
         /// The list of files to observe.
         /// This can also be a list of directories,
         /// in which case all files in those directories are tracked.
-        #[arg(short, long, default_value = ".", num_args = 1..)]
+        /// Defaults to . unless --stdin is used.
+        #[arg(
+            short,
+            long,
+            num_args = 1..,
+            default_value = ".",
+            default_value_if("stdin", "true", None)
+        )]
         paths: Vec<PathBuf>,
+        /// Also read paths from stdin, one per line.
+        #[arg(long)]
+        stdin: bool,
+        /// Paths read from stdin are separated by NUL instead of newlines.
+        #[arg(short = 'z', requires = "stdin")]
+        nul: bool,
+        /// Print what would be staged instead of staging it.
+        #[arg(long)]
+        dry_run: bool,
+        /// Don't print how many changes were staged.
+        #[arg(short, long)]
+        quiet: bool,
+        /// Go through the changes one hunk at a time, and only observe the ones picked.
+        #[arg(long, conflicts_with_all = ["stdin", "dry_run"])]
+        patch: bool,
     },
     /// Commit the changes observed.
     Track {
         /// The commit message.
-        /// If this option is not used, vim is opened.
-        /// Commit messages cannot be empty.
+        /// If neither this nor --file is used, the commit has no message.
+        /// A message given with this or --file cannot be empty, and its lines are joined into one.
         #[arg(short, long, default_value = None)]
         message: Option<String>,
+        /// Read the commit message from this file.
+        /// Its lines are joined into one, blank ones are dropped.
+        #[arg(short = 'F', long = "file", value_name = "FILE", conflicts_with = "message")]
+        message_file: Option<PathBuf>,
         /// Equivalent to calling gyat observe before this command.
         #[arg(short = 'a', long)]
         track_all: bool,
+        /// Replace the last commit instead of adding a new one on top of it.
+        /// Keeps the last commit's message unless a new one is given.
+        #[arg(long)]
+        amend: bool,
+        /// Commit even if the tree ends up the same as the parent's.
+        #[arg(long)]
+        allow_empty: bool,
+        /// Pick the hunks to track first, from the whole repository, see `observe --patch`.
+        #[arg(short, long, conflicts_with = "track_all")]
+        patch: bool,
     },
     /// Fall back to a previous track
     Fallback {
-        /// the hash value of the tracked change (required argument)
+        /// the hash value (or a tag) of the tracked change (required argument).
+        /// HEAD~n and HEAD^ (or any other ref followed by them) go back n or 1 commits.
         #[arg(required = true)]
         commit_hash: Option<String>,
+        /// Only restore the files under these paths. HEAD stays where it is.
+        paths: Vec<PathBuf>,
+        /// Fall back even if it overwrites changes that aren't tracked yet. They're lost.
+        #[arg(short, long)]
+        force: bool,
     },
     // this prints a log of all changes. We may actually implement this right after track
     Wood {
+        /// The commit or tag to start from, eg, HEAD~2. Defaults to HEAD.
+        start: Option<String>,
         /// Maximum number of lines to display the log
         #[arg(short = 'n', long, default_value = "10")]
         lines: usize,
+        /// Print the abbreviated hash and the first line of the commit message.
+        #[arg(long)]
+        oneline: bool,
+        /// Print the oldest commit first.
+        #[arg(long)]
+        reverse: bool,
+        /// Check that every commit's tree and blobs exist, and warn about the ones that don't.
+        #[arg(long)]
+        verify: bool,
+        /// Draw the history of every branch as a graph. With a start, only its history.
+        #[arg(long, conflicts_with = "reverse")]
+        graph: bool,
+    },
+    /// Show every movement of HEAD, newest first.
+    /// Use this to find the commit you were on before a fallback.
+    Reflog,
+    /// Move HEAD (and the current branch) to another commit, keeping the working tree as is.
+    /// The observed changes are dropped.
+    Reset {
+        /// The commit (or tag, or abbreviated hash) to move to. HEAD~n and HEAD^ work too.
+        commit: String,
+        /// Also make the working tree match the commit, throwing away the changes that aren't
+        /// tracked. Files that were never tracked are left alone.
+        #[arg(long)]
+        hard: bool,
+    },
+    /// Show the commit that last changed each line of a file.
+    Blame {
+        /// The file, which must be tracked in HEAD.
+        path: PathBuf,
+    },
+    /// Show what's observed but not tracked, and what changed since it was observed.
+    Status {
+        /// Print one `XY path` line per path instead, for scripts.
+        #[arg(short, long)]
+        short: bool,
+    },
+    /// Tag the commit HEAD points to.
+    Tag {
+        /// The tag name.
+        name: String,
+        /// Make an annotated tag with this message.
+        #[arg(short, long, default_value = None)]
+        message: Option<String>,
+    },
+    /// Show how many objects are stored, how much space the blobs take, and how many objects
+    /// can't be reached from HEAD anymore.
+    Stats,
+    /// Print the hash of every stored object of one kind, for debugging the object store.
+    #[command(group(clap::ArgGroup::new("kind").required(true)))]
+    ListObjects {
+        /// File contents.
+        #[arg(long, group = "kind")]
+        blobs: bool,
+        /// Directories.
+        #[arg(long, group = "kind")]
+        trees: bool,
+        /// Commits.
+        #[arg(long, group = "kind")]
+        commits: bool,
     },
 }
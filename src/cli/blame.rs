@@ -0,0 +1,134 @@
+use std::path::Path;
+
+use crate::Result;
+use gyat::{
+    diff,
+    hash::Oid,
+    objects::{self, CommitObject},
+    repo::Repository,
+    root, utils,
+};
+
+/// Prints every line of `path` as of HEAD, along with the commit that last changed it:
+/// `abbrev-hash author date | line`.
+///
+/// Walks the commits from HEAD through their parents. Each time a line can't be traced back to
+/// the parent's version of the file, the commit is blamed for it.
+///
+/// * `path`: the file to blame. Must be a text file tracked in HEAD.
+pub fn blame(path: &Path) -> Result<()> {
+    let repo = Repository::open()?;
+    let utils::AllPaths {
+        repo_root,
+        gyat_path,
+        ..
+    } = repo.paths();
+    let path = root::repo_relative(path, &std::env::current_dir()?, repo_root)?;
+
+    let head = utils::read_head(gyat_path)?.ok_or("There is no commit yet")?;
+    let mut commit_hash = head.parse()?;
+    let mut commit = objects::read_commit_content(&commit_hash)?;
+    let mut blob = repo.find_blob(&commit.root, &path)?.ok_or_else(|| {
+        format!(
+            "{} is not tracked in HEAD",
+            repo.display_path(&path).display()
+        )
+    })?;
+    let lines = text_lines(&repo, &blob, &path)?;
+
+    // the commits blamed so far, and which of them each line of `lines` belongs to.
+    let mut commits: Vec<(Oid, CommitObject)> = Vec::new();
+    let mut owners: Vec<Option<usize>> = vec![None; lines.len()];
+    // where each line not blamed yet is in `version`, the file as of `commit`.
+    let mut positions: Vec<Option<usize>> = (0..lines.len()).map(Some).collect();
+    let mut version = lines.clone();
+    loop {
+        let parent = match commit.parent {
+            Some(p) => Some((p, objects::read_commit_content(&p)?)),
+            None => None,
+        };
+        let parent_blob = match &parent {
+            Some((_, parent_commit)) => repo.find_blob(&parent_commit.root, &path)?,
+            None => None,
+        };
+        // for each line of `version`, the same line in the parent's version.
+        let (parent_version, matches) = match parent_blob {
+            Some(pb) if pb == blob => (version.clone(), (0..version.len()).map(Some).collect()),
+            Some(pb) => {
+                let parent_version = text_lines(&repo, &pb, &path)?;
+                let matches = diff::matching_lines(&parent_version, &version);
+                (parent_version, matches)
+            }
+            None => (Vec::new(), vec![None; version.len()]),
+        };
+
+        let blamed = commits.len();
+        commits.push((commit_hash, commit));
+        let mut remaining = false;
+        for (owner, position) in owners.iter_mut().zip(positions.iter_mut()) {
+            let Some(p) = *position else {
+                continue;
+            };
+            match matches[p] {
+                Some(parent_p) => {
+                    *position = Some(parent_p);
+                    remaining = true;
+                }
+                None => {
+                    *owner = Some(blamed);
+                    *position = None;
+                }
+            }
+        }
+
+        // `remaining` can only be true if there's a parent with the file in it.
+        match (parent, parent_blob) {
+            (Some((parent_hash, parent_commit)), Some(pb)) if remaining => {
+                commit_hash = parent_hash;
+                commit = parent_commit;
+                blob = pb;
+                version = parent_version;
+            }
+            _ => break,
+        }
+    }
+
+    let authors: Vec<&str> = commits
+        .iter()
+        .map(|(_, c)| match &c.author {
+            // the name is enough here.
+            Some(author) => author.split(" <").next().unwrap_or(author),
+            None => "unknown",
+        })
+        .collect();
+    let author_width = authors.iter().map(|a| a.chars().count()).max().unwrap_or(0);
+    for (line, owner) in lines.iter().zip(owners) {
+        let owner = owner.expect("every line is blamed once the walk is over");
+        let (hash, commit) = &commits[owner];
+        println!(
+            "{} {:<author_width$} {} | {}",
+            &hash.to_string()[..7],
+            authors[owner],
+            commit.date.as_deref().unwrap_or_default(),
+            line
+        );
+    }
+
+    Ok(())
+}
+
+/// The lines of a blob.
+///
+/// # Return values
+/// - Err if the blob cannot be read, or isn't text.
+///
+/// * `repo`:
+/// * `blob_hash`:
+/// * `path`: only for the error message.
+fn text_lines(repo: &Repository, blob_hash: &Oid, path: &Path) -> Result<Vec<String>> {
+    let content = repo.read_blob(blob_hash)?;
+    match std::str::from_utf8(&content) {
+        Ok(text) if !text.contains('\0') => Ok(text.lines().map(str::to_string).collect()),
+        _ => Err(format!("{} is not a text file", repo.display_path(path).display()).into()),
+    }
+}
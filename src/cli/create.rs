@@ -1,11 +1,15 @@
-use std::{fs, path::PathBuf};
+use std::path::PathBuf;
 
 use gyat::root;
 
 use crate::Result;
 
 /// This create function takes in an Option<String> for name to handle both cases when name is given or not
-pub fn create(name: &Option<String>) -> Result<()> {
+///
+/// * `name`: the directory to create the repository in, or the current directory if None.
+/// * `force`: create the repository even if one of the parent directories is already a gyat
+///   repository.
+pub fn create(name: &Option<String>, force: bool) -> Result<()> {
     // Validate the repository name
     let repo_path = match name {
         Some(ref name) => {
@@ -18,35 +22,60 @@ pub fn create(name: &Option<String>) -> Result<()> {
         None => std::env::current_dir()?,
     };
 
-    // Create the directory if a name was provided and it doesn't exist
-    if name.is_some() && !repo_path.exists() {
-        fs::create_dir(&repo_path)?;
-    } else if repo_path.exists() && !repo_path.is_dir() {
-        return Err(format!("{} exists but is not a directory", repo_path.display()).into());
-    }
-
-    // Create .gyat directory
-    let gyat_path = repo_path.join(".gyat");
-    if root::is_repo(&repo_path) {
-        return Err("This is already inside a .gyat repository".into());
+    // nested repositories confuse `root::get_repo_root`, which just stops at the innermost one.
+    // `repo_path` may not exist yet, so start from the closest ancestor that does.
+    let absolute_path = std::env::current_dir()?.join(&repo_path);
+    if let Some(outer_root) = absolute_path
+        .ancestors()
+        .find(|p| p.exists())
+        .and_then(root::get_repo_root)
+    {
+        if absolute_path.canonicalize().is_ok_and(|p| p == outer_root) {
+            return Err("This is already a .gyat repository".into());
+        }
+        if !force {
+            return Err(format!(
+                "{} is inside the gyat repository at {}, use --force to create one anyway",
+                repo_path.display(),
+                outer_root.display()
+            )
+            .into());
+        }
     }
-    fs::create_dir(&gyat_path)?;
-
-    // Create the internal structure
-    let gyat_path_commits = gyat_path.join("commits");
-    let gyat_path_dirs = gyat_path.join("dirs");
-    let gyat_path_files = gyat_path.join("files");
-    let gyat_path_head = gyat_path.join("HEAD");
-
-    fs::create_dir(gyat_path_commits)?;
-    fs::create_dir(gyat_path_dirs)?;
-    fs::create_dir(gyat_path_files)?;
-    fs::write(gyat_path.join("index"), "")?;
-    fs::write(gyat_path_head, "")?;
 
+    let repo_root = root::init_repo(&repo_path)?;
     println!(
         "Initialized empty gyat repository in {}",
-        repo_path.display()
+        repo_root.display()
     );
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn nested_create_test() {
+        let tmp = tempfile::tempdir().unwrap();
+        let outer = tmp.path().join("outer");
+        let outer_name = Some(outer.to_string_lossy().into_owned());
+        create(&outer_name, false).unwrap();
+
+        // directly inside, and a few levels down where the directories don't exist yet.
+        for inner in [outer.join("inner"), outer.join("a").join("b")] {
+            let inner_name = Some(inner.to_string_lossy().into_owned());
+            assert!(create(&inner_name, false).is_err());
+            assert!(!inner.exists());
+        }
+        // the repository itself can't be created twice, even with --force.
+        assert!(create(&outer_name, true).is_err());
+
+        let inner = outer.join("inner");
+        create(&Some(inner.to_string_lossy().into_owned()), true).unwrap();
+        assert_eq!(
+            root::get_repo_root(&inner),
+            Some(inner.canonicalize().unwrap())
+        );
+    }
+}
@@ -1,8 +1,15 @@
 use std::fs::File;
+use std::io::{BufWriter, Write};
 use std::path::Path;
-use std::{collections::HashMap, env::current_dir, path::PathBuf};
+use std::path::PathBuf;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::ops::Bound::{Excluded, Unbounded};
 use gyat::{
-    fs, hash, objects
+    fs::{self, ChangeType, IndexEntry},
+    hash::Oid,
+    objects,
+    repo::Repository,
+    utils,
 };
 
 use std::fs::create_dir_all;
@@ -10,171 +17,488 @@ use std::fs::remove_file;
 use std::fs::remove_dir;
 
 use crate::cli::observe::observe;
-use crate::cli::track::track;
+use crate::cli::track::track_with_action;
 
 use crate::Result;
 
 /// - Requirement: 
 ///     + The argument (specific commit that we want to get to) is provided
 ///     + There must be a previous commit
+///
 /// Behaviour:
 /// - Gets the root tree hash from the target commit
 /// - Gets all blobs (files) from that commit's tree
 /// - Cleans up the working directory by removing files that aren't in the target commit
 /// - Creates or updates files based on the target commit's blobs
 /// - Updates HEAD to point to the checked-out commit
-pub fn fallback(commit_hash: Option<&String>) -> Result<()> {
-    let repo_path = current_dir()?;
-    let gyat_path = repo_path.join(".gyat");
+///
+/// With `paths`, only files under them are restored, and HEAD stays where it is. What's restored
+/// shows up as changes to observe, like any other edit.
+///
+/// Refuses to run if a file it would touch has changes that aren't tracked yet, unless `force`
+/// is set, in which case those changes are lost.
+pub fn fallback(commit_hash: Option<&String>, paths: &[PathBuf], force: bool) -> Result<()> {
+    // every tree and blob below is read through this, so shared ones are only read once.
+    let repo = Repository::open()?;
+    let gyat_path = &repo.paths().gyat_path;
+    // tags, abbreviated hashes and HEAD~n are fine too.
+    let commit_hash =
+        objects::resolve_rev(&repo, commit_hash.ok_or("No commit to fall back to")?)?.to_string();
 
-    let head_blobs = match get_blobs_from_head(&gyat_path) {
-        Ok(blobs) => blobs,
-        Err(_) => return Ok(()) 
-    };
-
-    let commit_blobs = match get_blobs_from_commit(&gyat_path, commit_hash) {
-        Ok(blobs) => blobs,
-        Err(_) => return Ok(()) 
-    };
+    // nothing to fall back from without a commit yet. A HEAD or commit that can't be read is an
+    // error though, not nothing to do.
+    if repo.head_commit()?.is_none() {
+        return Ok(());
+    }
+    let head_root = get_root_from_head(gyat_path)?;
+    let commit_root = get_root_from_commit(gyat_path, Some(&commit_hash))?;
 
-    let changes = compare_trees(head_blobs, commit_blobs).unwrap();
+    let cwd = std::env::current_dir()?;
+    let scope = paths
+        .iter()
+        .map(|path| scope_path(path, &cwd, &repo.paths().repo_root))
+        .collect::<Result<Vec<_>>>()?;
+    let changes = compare_trees(&repo, &head_root, &commit_root, &scope)?;
+    if !force {
+        let local = fs::compute_changes(&repo, std::slice::from_ref(&repo.paths().repo_root))?;
+        let overwritten = overwritten_paths(&changes, &local);
+        if !overwritten.is_empty() {
+            let mut msg =
+                String::from("Your local changes to these files would be overwritten by fallback:");
+            for path in overwritten {
+                msg.push_str(&format!("\n\t{}", repo.display_path(path).display()));
+            }
+            msg.push_str("\nTrack them first, or use --force to throw them away.");
+            return Err(msg.into());
+        }
+    }
 
-    process_change(&changes)?;
+    process_change(&repo, &changes)?;
+    if !scope.is_empty() {
+        log_fallback_action(&repo, &commit_hash, changes)?;
+        return Ok(());
+    }
 
-    observe(&[PathBuf::from(".")])?;
-    track(&Some(format!("Fallback to the commit with commit_id {}", commit_hash.unwrap()).to_string()), true)?;
+    observe(
+        std::slice::from_ref(&repo.paths().repo_root),
+        false,
+        false,
+        false,
+        true,
+    )?;
+    track_with_action(
+        &Some(format!("Fallback to the commit with commit_id {}", commit_hash).to_string()),
+        &None,
+        true,
+        false,
+        // falling back to a commit with the same tree still moves HEAD.
+        true,
+        "fallback",
+    )?;
 
-    log_fallback_action(commit_hash.unwrap(), changes)?;
+    log_fallback_action(&repo, &commit_hash, changes)?;
 
     Ok(())
 }
 
-fn get_blobs_from_head(gyat_path: &PathBuf) -> Result<HashMap<PathBuf, [u8; 20]>> {
+/// Resolves a path given to `fallback`, like `root::repo_relative` does, except it doesn't have
+/// to exist: restoring a deleted file is the whole point.
+///
+/// # Return values
+/// - Err if the path is outside the repository.
+/// - Ok(PathBuf) otherwise, relative to the repository root. Empty for the root itself.
+///
+/// * `path`: relative to `cwd`, or absolute.
+/// * `cwd`:
+/// * `repo_root`:
+fn scope_path(path: &Path, cwd: &Path, repo_root: &Path) -> Result<PathBuf> {
+    match fs::normalize(&cwd.join(path)).strip_prefix(repo_root) {
+        Ok(relative) if !relative.starts_with(".gyat") => Ok(relative.to_path_buf()),
+        _ => Err(format!("{}: path is outside the repository", path.display()).into()),
+    }
+}
+
+fn get_root_from_head(gyat_path: &Path) -> Result<Oid> {
     if let Some(head_root) = fs::get_root_tree_hash(gyat_path, None)? {
-        // Get all blobs from the lastest commit's root tree
-        let head_blobs = objects::get_blobs_from_root(&hash::from_string(&head_root).unwrap())?;
-        
-        Ok(head_blobs)
+        // The lastest commit's root tree
+        Ok(head_root)
     } else {
         Err("There is no previous commit".into())
     }
 }
 
-fn get_blobs_from_commit(gyat_path: &PathBuf, commit_hash: Option<&String>) -> Result<HashMap<PathBuf, [u8; 20]>> {
+fn get_root_from_commit(gyat_path: &Path, commit_hash: Option<&String>) -> Result<Oid> {
     if let Some(commit_root) = fs::get_root_tree_hash(gyat_path, commit_hash)? {
-        // Get all blobs from the specified commit's root tree
-        let commit_blobs = objects::get_blobs_from_root(&hash::from_string(&commit_root).unwrap())?;
-        
-        Ok(commit_blobs)
+        // The specified commit's root tree
+        Ok(commit_root)
     } else {
         Err("There is no such commit".into())
     }
 }
 
+/// The local changes `changes` would throw away, eg, files edited (or created) since HEAD that
+/// would be written over or deleted.
+///
+/// A path being written also takes out whatever is under it (a directory where a file is restored)
+/// and above it (a file where a directory is needed), so local changes there count too.
+///
+/// * `changes`: from `compare_trees`.
+/// * `local`: the working tree against HEAD, see `fs::compute_changes`.
+fn overwritten_paths<'a>(changes: &Changes, local: &'a [IndexEntry]) -> Vec<&'a PathBuf> {
+    let written: BTreeSet<&Path> = changes
+        .to_add
+        .iter()
+        .chain(&changes.to_modify)
+        .map(|(path, _)| path.as_path())
+        .collect();
+    let deleted: HashSet<&PathBuf> = changes.to_delete.iter().collect();
+    let mut ret: Vec<_> = local
+        .iter()
+        .map(|entry| &entry.path)
+        .filter(|path| {
+            // paths under `path` sort right after it.
+            deleted.contains(path)
+                || path.ancestors().any(|p| written.contains(p))
+                || written
+                    .range::<&Path, _>((Excluded(path.as_path()), Unbounded))
+                    .next()
+                    .is_some_and(|w| w.starts_with(path))
+        })
+        .collect();
+    ret.sort();
+    ret
+}
+
 #[derive(Debug, Hash, PartialEq, Eq)]
-struct Changes {
-    to_add: Vec<(PathBuf, [u8; 20])>,
-    to_modify: Vec<(PathBuf, [u8; 20])>,
+pub struct Changes {
+    to_add: Vec<(PathBuf, Oid)>,
+    to_modify: Vec<(PathBuf, Oid)>,
     to_delete: Vec<PathBuf>,
 }
 
-fn compare_trees(head_blobs: HashMap<PathBuf, [u8; 20]>, commit_blobs: HashMap<PathBuf, [u8; 20]>) -> Result<Changes> {
+impl Changes {
+    /// Also throws away `local`, so the working tree ends up matching the target commit even
+    /// where it didn't match HEAD. Files in neither HEAD nor the target, ie, never tracked, are
+    /// left alone.
+    ///
+    /// * `local`: the working tree against HEAD, see `fs::compute_changes`.
+    /// * `target_blobs`: every blob of the commit the changes go to.
+    pub fn undo_local(&mut self, local: &[IndexEntry], target_blobs: &BTreeMap<PathBuf, Oid>) {
+        let planned: HashSet<PathBuf> = self
+            .to_add
+            .iter()
+            .chain(&self.to_modify)
+            .map(|(path, _)| path)
+            .chain(&self.to_delete)
+            .cloned()
+            .collect();
+        // anything HEAD has that the target doesn't is already deleted, so only the target's
+        // version needs to be put back.
+        for entry in local.iter().filter(|entry| !planned.contains(&entry.path)) {
+            if let Some(hash) = target_blobs.get(&entry.path) {
+                self.to_modify.push((entry.path.clone(), *hash));
+            }
+        }
+        self.to_modify.sort();
+    }
+}
+
+/// Splits what changes from `head_root` to `commit_root` into what to add, modify and delete.
+/// Each list is sorted by path.
+///
+/// * `repo`:
+/// * `head_root`:
+/// * `commit_root`:
+/// * `scope`: only paths under one of these count, relative to the repository root. Empty for
+///   every path.
+pub fn compare_trees(
+    repo: &Repository,
+    head_root: &Oid,
+    commit_root: &Oid,
+    scope: &[PathBuf],
+) -> Result<Changes> {
     let mut changes = Changes {
         to_add: Vec::new(),
         to_modify: Vec::new(),
         to_delete: Vec::new(),
     };
-
-    // Find files that need to be added back (exist in the specified commit but not in HEAD anymore) for remodified  
-    for (path, commit_hash) in commit_blobs.iter() {
-        match head_blobs.get(path) {
-            Some(head_hash) => {
-                // File exists in both commits
-                if head_hash != commit_hash {
-                    // Hash is different, so file was modified
-                    changes.to_modify.push((path.clone(), *commit_hash));
-                }
-            }
-            None => {
-                // File only exists in target commit, so we need to add it back
-                changes.to_add.push((path.clone(), *commit_hash));
-            }
+    let blobs = |root: &Oid| -> Result<BTreeMap<PathBuf, Oid>> {
+        let mut blobs = repo.get_blobs_from_root(root)?;
+        if !scope.is_empty() {
+            blobs.retain(|path, _| scope.iter().any(|s| path.starts_with(s)));
         }
-    }
+        Ok(blobs)
+    };
 
-    // Find files that needed to be deleted
-    for (path, _head_hash) in head_blobs.iter() {
-        if !commit_blobs.contains_key(path) {
-            // File exists in HEAD but not in target commit, so it is to delete
-            changes.to_delete.push(path.clone());
+    // Going from HEAD to the specified commit:
+    // - New files exist in the specified commit but not in HEAD anymore, so they are added back.
+    // - Modified files get the content from the specified commit.
+    // - Deleted files exist in HEAD but not in the specified commit, so they are to delete.
+    let (head_blobs, commit_blobs) = (blobs(head_root)?, blobs(commit_root)?);
+    for (change, path, _, commit_hash) in objects::diff_blobs(&head_blobs, &commit_blobs) {
+        match change {
+            ChangeType::New => changes.to_add.push((path, commit_hash.unwrap())),
+            ChangeType::Mod => changes.to_modify.push((path, commit_hash.unwrap())),
+            ChangeType::Del => changes.to_delete.push(path),
         }
     }
 
     Ok(changes)
 }
 
-fn process_change(changes: &Changes) -> Result<()> {
-    // Process added and modified files
-    for (path, hash) in &changes.to_add {
-        // Create parent directories if they don't exist
-        if let Some(parent) = path.parent() {
-            create_dir_all(parent)?;
+/// Makes the working tree match `changes`: every delete first, then every add and modify, each in
+/// path order, so the same changes always touch the files in the same order.
+/// Paths are relative to the repository root, wherever the command was run from.
+pub fn process_change(repo: &Repository, changes: &Changes) -> Result<()> {
+    let repo_root = &repo.paths().repo_root;
+    // Remove deleted files first, cleaning up their parents must not take out empty directories
+    // that are added back below
+    for path in &changes.to_delete {
+        let path = &repo_root.join(path);
+        // Check if file exists before attempting to remove
+        if path.is_dir() {
+            // only ever an empty directory, otherwise it would have blobs inside instead.
+            // If it's not empty anymore, leave it alone.
+            if remove_dir(path).is_ok() {
+                cleanup_empty_dirs(path.parent(), repo_root)?;
+            }
+        } else if path.exists() {
+            remove_file(path)?;
+            
+            // Try to remove empty parent directories
+            cleanup_empty_dirs(path.parent(), repo_root)?;
         }
-        // Create empty file and write the content
-        File::create(path)?;
-        let content = objects::read_blob(hash)?;
-        std::fs::write(path, content)?;
     }
 
     // Both added and modified files need their contents updated
-    for (path, hash) in &changes.to_modify {
-        // Read blob content from object store
-        let content = objects::read_blob(hash)?;
-        
+    let mut progress =
+        utils::Progress::new("restoring", changes.to_add.len() + changes.to_modify.len());
+    for (path, hash) in changes.to_add.iter().chain(&changes.to_modify) {
+        progress.tick();
+        let path = &repo_root.join(path);
+        // Something else may be in the way, eg, a file that used to be a directory
+        if path.is_dir() && *hash != objects::EMPTY_DIR {
+            std::fs::remove_dir_all(path)?;
+        } else if path.is_file() && *hash == objects::EMPTY_DIR {
+            remove_file(path)?;
+        }
+
+        // Empty directories have no content, they just need to exist
+        if *hash == objects::EMPTY_DIR {
+            create_dir_all(path)?;
+            continue;
+        }
+
         // Create parent directories if they don't exist
         if let Some(parent) = path.parent() {
             create_dir_all(parent)?;
         }
-        
-        // Write content to file
-        File::create(path)?;
-        std::fs::write(path, content)?;
-    }
 
-    // Remove deleted files
-    for path in &changes.to_delete {
-        // Check if file exists before attempting to remove
-        if path.exists() {
-            remove_file(path)?;
-            
-            // Try to remove empty parent directories
-            cleanup_empty_dirs(path.parent())?;
-        }
+        // Stream blob content from object store straight into the file
+        let mut file = BufWriter::new(File::create(path)?);
+        repo.read_blob_to(hash, &mut file)?;
+        file.flush()?;
     }
+    progress.finish();
 
     Ok(())
 }
 
-// Helper function to recursively remove empty directories
-fn cleanup_empty_dirs(dir: Option<&Path>) -> Result<()> {
-    let Some(dir) = dir else {
+// Helper function to recursively remove empty directories, stopping at the repository root
+fn cleanup_empty_dirs(dir: Option<&Path>, repo_root: &Path) -> Result<()> {
+    let Some(dir) = dir.filter(|dir| *dir != repo_root) else {
         return Ok(());
     };
 
     // Try to remove directory and continue with parent if successful
-    match remove_dir(dir) {
-        Ok(_) => cleanup_empty_dirs(dir.parent())?,
-        Err(_) => () // Directory not empty or already removed
+    // Err means directory not empty or already removed
+    if remove_dir(dir).is_ok() {
+        cleanup_empty_dirs(dir.parent(), repo_root)?;
     }
 
     Ok(())
 }
 
-fn log_fallback_action(commit_id: &String, changes: Changes) -> Result<()> {
+fn log_fallback_action(repo: &Repository, commit_id: &String, changes: Changes) -> Result<()> {
     // Implementation for logging the action taken
     println!("Fallback to commit {}", commit_id);
-    println!("Added files: {:?}", changes.to_add);
-    println!("Modified files: {:?}", changes.to_modify);
-    println!("Deleted files: {:?}", changes.to_delete);
+    let added = changes.to_add.iter().map(|(path, _)| path);
+    let modified = changes.to_modify.iter().map(|(path, _)| path);
+    println!("Added files: {}", display_list(repo, added));
+    println!("Modified files: {}", display_list(repo, modified));
+    println!(
+        "Deleted files: {}",
+        display_list(repo, changes.to_delete.iter())
+    );
     Ok(())
 }
+
+/// `a, b, c`, each path relative to the current directory.
+fn display_list<'a>(repo: &Repository, paths: impl Iterator<Item = &'a PathBuf>) -> String {
+    let paths: Vec<_> = paths
+        .map(|path| repo.display_path(path).display().to_string())
+        .collect();
+    paths.join(", ")
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::OsStr;
+
+    use super::*;
+    use gyat::{
+        objects::{FType, FileObjectRef},
+        root,
+    };
+
+    #[test]
+    fn scoped_compare_trees_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::open_in(&root::init_repo(dir.path()).unwrap()).unwrap();
+        let write_tree = |children: &[(FType, Oid, &str)]| {
+            let content =
+                objects::format_tree_content(children.iter().map(|(ftype, hash, component)| {
+                    FileObjectRef {
+                        ftype: *ftype,
+                        hash,
+                        component: OsStr::new(component),
+                    }
+                }));
+            let tree_hash = Oid::of(&content);
+            std::fs::write(repo.paths().dirs_path.join(tree_hash.to_string()), content).unwrap();
+            tree_hash
+        };
+        let (old, new) = (Oid::of(b"scoped old"), Oid::of(b"scoped new"));
+        let head_src = write_tree(&[(FType::Blob, old, "lib.rs"), (FType::Blob, old, "gone.rs")]);
+        let head = write_tree(&[(FType::Tree, head_src, "src"), (FType::Blob, old, "README")]);
+        let commit_src = write_tree(&[(FType::Blob, new, "lib.rs")]);
+        let commit = write_tree(&[
+            (FType::Tree, commit_src, "src"),
+            (FType::Blob, new, "README"),
+            (FType::Blob, new, "LICENSE"),
+        ]);
+
+        // everything outside `src` is left alone.
+        let changes = compare_trees(&repo, &head, &commit, &[PathBuf::from("src")]).unwrap();
+        assert_eq!(
+            changes,
+            Changes {
+                to_add: vec![],
+                to_modify: vec![(PathBuf::from("src/lib.rs"), new)],
+                to_delete: vec![PathBuf::from("src/gone.rs")],
+            }
+        );
+        let changes = compare_trees(&repo, &head, &commit, &[PathBuf::from("LICENSE")]).unwrap();
+        assert_eq!(changes.to_add, [(PathBuf::from("LICENSE"), new)]);
+        assert!(changes.to_modify.is_empty() && changes.to_delete.is_empty());
+        // no scope is the whole tree.
+        let changes = compare_trees(&repo, &head, &commit, &[]).unwrap();
+        assert_eq!(changes.to_modify.len(), 2);
+    }
+
+    #[test]
+    fn scoped_process_change_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo_root = root::init_repo(dir.path()).unwrap();
+        let repo = Repository::open_in(&repo_root).unwrap();
+        let write = |files: &[(&str, &str)]| {
+            for (path, content) in files {
+                let path = repo_root.join(path);
+                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                std::fs::write(path, content).unwrap();
+            }
+        };
+        let snapshot = || {
+            let mut tree = gyat::dirtree::Tree::new_in(&repo_root).unwrap();
+            tree.add_path(&repo_root);
+            tree.to_object_file().unwrap()
+        };
+        let read = |path: &str| std::fs::read_to_string(repo_root.join(path)).ok();
+
+        write(&[
+            ("src/lib.rs", "old"),
+            ("src/gone.rs", "gone"),
+            ("README", "old"),
+        ]);
+        let commit = snapshot();
+        write(&[("src/lib.rs", "new"), ("README", "new"), ("LICENSE", "new")]);
+        std::fs::remove_file(repo_root.join("src/gone.rs")).unwrap();
+        let head = snapshot();
+        // a local edit outside the scope has to survive too.
+        write(&[("README", "edited")]);
+
+        let changes = compare_trees(&repo, &head, &commit, &[PathBuf::from("src")]).unwrap();
+        process_change(&repo, &changes).unwrap();
+        assert_eq!(read("src/lib.rs").as_deref(), Some("old"));
+        assert_eq!(read("src/gone.rs").as_deref(), Some("gone"));
+        assert_eq!(read("README").as_deref(), Some("edited"));
+        assert_eq!(read("LICENSE").as_deref(), Some("new"));
+    }
+
+    #[test]
+    fn scope_path_test() {
+        let repo_root = Path::new("/repo");
+        let scope = |path: &str| scope_path(Path::new(path), &repo_root.join("src"), repo_root);
+        // doesn't have to exist.
+        assert_eq!(scope("deleted.rs").unwrap(), Path::new("src/deleted.rs"));
+        assert_eq!(scope("../docs").unwrap(), Path::new("docs"));
+        assert_eq!(scope("..").unwrap(), Path::new(""));
+        assert!(scope("../..").is_err());
+        assert!(scope("../.gyat/index").is_err());
+    }
+
+    #[test]
+    fn overwritten_paths_test() {
+        let hash = Oid::of(b"content");
+        let changes = Changes {
+            to_add: vec![(PathBuf::from("added.txt"), hash)],
+            to_modify: vec![
+                (PathBuf::from("modified.txt"), hash),
+                (PathBuf::from("clean.txt"), hash),
+            ],
+            to_delete: vec![PathBuf::from("deleted.txt")],
+        };
+        let entry = |path: &str, change| IndexEntry {
+            perm: b'1',
+            hash,
+            path: PathBuf::from(path),
+            change,
+        };
+        let local = [
+            // created locally where fallback would put a file.
+            entry("added.txt", ChangeType::New),
+            entry("modified.txt", ChangeType::Mod),
+            entry("deleted.txt", ChangeType::Mod),
+            // fallback doesn't touch this one.
+            entry("other.txt", ChangeType::Mod),
+        ];
+        assert_eq!(
+            overwritten_paths(&changes, &local),
+            [
+                Path::new("added.txt"),
+                Path::new("deleted.txt"),
+                Path::new("modified.txt")
+            ]
+        );
+        assert!(overwritten_paths(&changes, &[]).is_empty());
+
+        let changes = Changes {
+            to_add: vec![(PathBuf::from("notes"), hash)],
+            to_modify: vec![(PathBuf::from("docs/guide.md"), hash)],
+            to_delete: vec![],
+        };
+        let local = [
+            // an untracked directory where a file is restored.
+            entry("notes/todo.txt", ChangeType::New),
+            // an untracked file where a directory is needed.
+            entry("docs", ChangeType::New),
+            // only the names start the same.
+            entry("notes.txt", ChangeType::New),
+            entry("docs-old/guide.md", ChangeType::New),
+        ];
+        assert_eq!(
+            overwritten_paths(&changes, &local),
+            [Path::new("docs"), Path::new("notes/todo.txt")]
+        );
+    }
+}
@@ -0,0 +1,292 @@
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+
+use gyat::{hash::Oid, objects::CommitObject, repo::Repository};
+
+use crate::Result;
+
+/// A commit as `wood --graph` lays it out.
+///
+/// * `hash`:
+/// * `parents`: only the ones that are part of the graph.
+/// * `label`: what's printed next to it, eg, `abc1234 message`.
+struct GraphCommit {
+    hash: Oid,
+    parents: Vec<Oid>,
+    label: String,
+}
+
+/// Prints the commits reachable from `starts` as an ASCII graph, like `git log --graph`, newest
+/// first: `*` for each commit, and `|`, `/`, `\` for the lines between them.
+///
+/// # Return values
+/// - Err if a start can't be read. Commits further down that can't be read just end their line.
+///
+/// * `repo`:
+/// * `starts`: where to walk from, eg, HEAD and every branch.
+/// * `lines`: the maximum number of commits to print, the walk stops there too.
+/// * `label`: what's printed next to a commit.
+pub fn print_graph(
+    repo: &Repository,
+    starts: &[Oid],
+    lines: usize,
+    label: impl Fn(&Oid, CommitObject) -> String,
+) -> Result<()> {
+    let commits = walk(repo, starts, lines, label)?;
+    for line in render(&topo_order(commits)) {
+        println!("{line}");
+    }
+    Ok(())
+}
+
+/// Breadth-first walk from `starts`, reading up to `limit` commits.
+///
+/// # Return value
+/// The commits, in the order they were found. Parents that weren't reached are dropped.
+///
+/// * `repo`:
+/// * `starts`:
+/// * `limit`:
+/// * `label`:
+fn walk(
+    repo: &Repository,
+    starts: &[Oid],
+    limit: usize,
+    label: impl Fn(&Oid, CommitObject) -> String,
+) -> Result<Vec<GraphCommit>> {
+    let mut ret = Vec::new();
+    let mut seen: HashSet<Oid> = HashSet::new();
+    let mut queue: VecDeque<Oid> = starts.iter().copied().collect();
+    while let Some(hash) = queue.pop_front() {
+        if ret.len() >= limit {
+            break;
+        }
+        if !seen.insert(hash) {
+            continue;
+        }
+        let commit = match repo.read_commit(&hash) {
+            Ok(commit) => commit,
+            Err(e) if starts.contains(&hash) => return Err(e),
+            Err(e) => {
+                eprintln!("Stopped at commit {hash}: {e}");
+                continue;
+            }
+        };
+        // a commit only has one parent for now, this is ready for more.
+        let parents: Vec<Oid> = commit.parent.into_iter().collect();
+        queue.extend(&parents);
+        ret.push(GraphCommit {
+            hash,
+            label: label(&hash, commit),
+            parents,
+        });
+    }
+
+    let reached: HashSet<Oid> = ret.iter().map(|c| c.hash).collect();
+    for commit in &mut ret {
+        commit.parents.retain(|p| reached.contains(p));
+    }
+    Ok(ret)
+}
+
+/// Orders the commits so each one comes before its parents. Otherwise they keep the order they
+/// were found in, so the newest stay on top.
+///
+/// * `commits`: as returned by `walk`.
+fn topo_order(commits: Vec<GraphCommit>) -> Vec<GraphCommit> {
+    let index: HashMap<Oid, usize> = commits
+        .iter()
+        .enumerate()
+        .map(|(i, c)| (c.hash, i))
+        .collect();
+    let mut children = vec![0usize; commits.len()];
+    for commit in &commits {
+        for parent in &commit.parents {
+            children[index[parent]] += 1;
+        }
+    }
+
+    let mut ready: BTreeSet<usize> = (0..commits.len()).filter(|i| children[*i] == 0).collect();
+    let mut order = Vec::with_capacity(commits.len());
+    while let Some(i) = ready.pop_first() {
+        order.push(i);
+        for parent in &commits[i].parents {
+            let p = index[parent];
+            children[p] -= 1;
+            if children[p] == 0 {
+                ready.insert(p);
+            }
+        }
+    }
+
+    let mut commits: Vec<Option<GraphCommit>> = commits.into_iter().map(Some).collect();
+    order
+        .into_iter()
+        .map(|i| commits[i].take().expect("each commit is ordered once"))
+        .collect()
+}
+
+/// Draws the graph, one string per line.
+///
+/// Every column is waiting for a commit. A commit takes the first column waiting for it (or a new
+/// one), which then waits for its first parent. Other parents get new columns, and columns that
+/// end up waiting for the same commit are joined.
+///
+/// * `commits`: each one before its parents, see `topo_order`.
+fn render(commits: &[GraphCommit]) -> Vec<String> {
+    let mut ret = Vec::new();
+    let mut columns: Vec<Oid> = Vec::new();
+    for commit in commits {
+        if !columns.contains(&commit.hash) {
+            columns.push(commit.hash);
+        }
+        // columns waiting for this commit that weren't next to each other, so weren't joined yet.
+        while let Some(dup) = columns
+            .iter()
+            .rposition(|c| *c == commit.hash)
+            .filter(|dup| columns[..*dup].contains(&commit.hash))
+        {
+            columns.remove(dup);
+            ret.push(shift_left(columns.len() + 1, dup, dup));
+        }
+        let col = columns
+            .iter()
+            .position(|c| *c == commit.hash)
+            .expect("added above");
+        let marks: Vec<&str> = (0..columns.len())
+            .map(|i| if i == col { "*" } else { "|" })
+            .collect();
+        ret.push(format!("{} {}", marks.join(" "), commit.label));
+
+        match commit.parents.split_first() {
+            None => {
+                columns.remove(col);
+                // the columns after it take its place.
+                if col < columns.len() {
+                    ret.push(shift_left(columns.len() + 1, col, col + 1));
+                }
+            }
+            Some((first, rest)) => {
+                columns[col] = *first;
+                for (i, parent) in rest.iter().enumerate() {
+                    ret.push(shift_right(columns.len(), col + i));
+                    columns.insert(col + i + 1, *parent);
+                }
+            }
+        }
+
+        // join the columns waiting for the same commit, if they're next to each other. Others
+        // are joined once that commit comes.
+        while let Some(dup) = (1..columns.len()).rfind(|j| columns[*j - 1] == columns[*j]) {
+            columns.remove(dup);
+            ret.push(shift_left(columns.len() + 1, dup, dup));
+        }
+    }
+    ret
+}
+
+/// `|` for the columns before `keep`, then a `/` for `first_moving` and each column after it,
+/// which all move one to the left.
+///
+/// * `width`: the number of columns before moving.
+/// * `keep`: the columns that stay where they are.
+/// * `first_moving`: `keep` if that column is joining the one on its left, `keep + 1` if it just
+///   ended.
+fn shift_left(width: usize, keep: usize, first_moving: usize) -> String {
+    let mut line: Vec<char> = vec![' '; 2 * width - 1];
+    for k in 0..keep {
+        line[2 * k] = '|';
+    }
+    for k in first_moving..width {
+        line[2 * k - 1] = '/';
+    }
+    line.into_iter().collect::<String>().trim_end().to_string()
+}
+
+/// `|` for the columns up to `at`, then a `\` for the new column right after it and for each
+/// column after that, which all move one to the right.
+///
+/// * `width`: the number of columns before moving.
+/// * `at`: the column the new one branches off.
+fn shift_right(width: usize, at: usize) -> String {
+    let mut line: Vec<char> = vec![' '; 2 * width + 1];
+    for k in 0..=at {
+        line[2 * k] = '|';
+    }
+    for k in at..width {
+        line[2 * k + 1] = '\\';
+    }
+    line.into_iter().collect::<String>().trim_end().to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Commits with their name as the label.
+    fn commits(spec: &[(&str, &[&str])]) -> Vec<GraphCommit> {
+        let oid = |name: &str| Oid::of(name.as_bytes());
+        spec.iter()
+            .map(|(name, parents)| GraphCommit {
+                hash: oid(name),
+                parents: parents.iter().map(|p| oid(p)).collect(),
+                label: name.to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn render_test() {
+        // two branches off `b`.
+        let graph = commits(&[("d", &["b"]), ("c", &["b"]), ("b", &["a"]), ("a", &[])]);
+        assert_eq!(render(&graph), ["* d", "| * c", "|/", "* b", "* a"]);
+
+        // a merge, and a second root.
+        let graph = commits(&[
+            ("m", &["b", "c"]),
+            ("c", &["a"]),
+            ("b", &["a"]),
+            ("a", &[]),
+            ("x", &[]),
+        ]);
+        assert_eq!(
+            render(&graph),
+            ["* m", "|\\", "| * c", "* | b", "|/", "* a", "* x"]
+        );
+
+        // a root lets the columns after it move left.
+        let graph = commits(&[("x", &["r"]), ("y", &["a"]), ("r", &[]), ("a", &[])]);
+        assert_eq!(render(&graph), ["* x", "| * y", "* | r", " /", "* a"]);
+
+        // `t1` and `t3` wait for `a` with `b` in between, they're only joined once `b` is.
+        let graph = commits(&[
+            ("t1", &["a"]),
+            ("t2", &["b"]),
+            ("t3", &["a"]),
+            ("b", &["a"]),
+            ("a", &[]),
+        ]);
+        assert_eq!(
+            render(&graph),
+            ["* t1", "| * t2", "| | * t3", "| * | b", "| |/", "|/", "* a"]
+        );
+        assert!(render(&[]).is_empty());
+    }
+
+    #[test]
+    fn topo_order_test() {
+        // found parent first, eg, from a branch that's behind another one.
+        let order = topo_order(commits(&[("a", &[]), ("c", &["b"]), ("b", &["a"])]));
+        let labels: Vec<&str> = order.iter().map(|c| c.label.as_str()).collect();
+        assert_eq!(labels, ["c", "b", "a"]);
+    }
+
+    #[test]
+    fn shift_test() {
+        assert_eq!(shift_left(2, 1, 1), "|/");
+        assert_eq!(shift_left(4, 2, 2), "| |/ /");
+        // the first column ended.
+        assert_eq!(shift_left(2, 0, 1), " /");
+        assert_eq!(shift_right(1, 0), "|\\");
+        assert_eq!(shift_right(3, 0), "|\\ \\ \\");
+    }
+}
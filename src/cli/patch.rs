@@ -0,0 +1,271 @@
+use std::{
+    io::{BufRead, Write},
+    path::PathBuf,
+};
+
+use gyat::{
+    diff::{self, Hunk},
+    fs::{self, ChangeType, IndexEntry},
+    objects,
+    repo::Repository,
+    utils,
+};
+
+use super::observe;
+use crate::Result;
+
+/// What the user answered to a prompt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Answer {
+    Yes,
+    No,
+    /// No, and don't ask about anything else either.
+    Quit,
+}
+
+/// `observe`, but asks about every change first: each hunk of a modified text file, and each
+/// added, deleted or binary file as a whole. Only what's answered with `y` is staged.
+///
+/// A file with only some of its hunks observed is stored right away, so `track` commits exactly
+/// what was picked, not what's in the working tree.
+///
+/// * `paths`: relative to the current directory, or absolute.
+/// * `painter`: removed lines are red, added ones green.
+pub fn patch(paths: &[PathBuf], painter: utils::Painter) -> Result<()> {
+    let repo = Repository::open()?;
+    let utils::AllPaths {
+        repo_root,
+        index_path,
+        ..
+    } = repo.paths();
+    let changes = fs::compute_changes(&repo, paths)?;
+    let head_root = repo.root_tree()?;
+
+    let mut input = std::io::stdin().lock();
+    let mut out = std::io::stdout().lock();
+    let mut staged = Vec::new();
+    for entry in changes {
+        let old = match (entry.change, &head_root) {
+            (ChangeType::Mod, Some(root)) => repo.find_blob(root, &entry.path)?,
+            _ => None,
+        };
+        let text = match old {
+            Some(old) if entry.hash != objects::EMPTY_DIR => {
+                let old = repo.read_blob(&old)?;
+                let new = std::fs::read(repo_root.join(&entry.path))?;
+                match (std::str::from_utf8(&old), std::str::from_utf8(&new)) {
+                    (Ok(o), Ok(n)) if !o.contains('\0') && !n.contains('\0') => {
+                        Some((o.to_string(), n.to_string()))
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+
+        let shown = repo.display_path(&entry.path);
+        let quit = match text {
+            Some((old, new)) => {
+                let old_lines: Vec<&str> = old.split_inclusive('\n').collect();
+                let new_lines: Vec<&str> = new.split_inclusive('\n').collect();
+                let hunks = diff::hunks(&old_lines, &new_lines);
+                writeln!(
+                    out,
+                    "{}",
+                    painter.bold(&format!("modified: {}", shown.display()))
+                )?;
+                let (accepted, quit) = choose_hunks(
+                    &old_lines, &new_lines, &hunks, &mut input, &mut out, painter,
+                )?;
+                if accepted.iter().all(|a| *a) {
+                    staged.push(entry);
+                } else if accepted.iter().any(|a| *a) {
+                    let picked = diff::apply_hunks(&old_lines, &new_lines, &hunks, &accepted);
+                    staged.push(IndexEntry {
+                        hash: objects::write_blob(picked.concat().as_bytes())?,
+                        ..entry
+                    });
+                }
+                quit
+            }
+            None => {
+                let what = match entry.change {
+                    ChangeType::New => "new",
+                    ChangeType::Mod => "modified",
+                    ChangeType::Del => "deleted",
+                };
+                writeln!(
+                    out,
+                    "{}",
+                    painter.bold(&format!("{what}: {}", shown.display()))
+                )?;
+                let answer = ask(&mut input, &mut out, "Observe this whole change")?;
+                if answer == Answer::Yes {
+                    staged.push(entry);
+                }
+                answer == Answer::Quit
+            }
+        };
+        if quit {
+            break;
+        }
+    }
+
+    println!("{}", observe::write_index(index_path, &staged)?);
+
+    Ok(())
+}
+
+/// Shows each hunk and asks whether to keep it.
+///
+/// # Return values
+/// - Err if `input` or `out` fails.
+/// - Ok((one answer per hunk, whether the user quit)) otherwise. Hunks after quitting are not
+///   kept.
+///
+/// * `old`: the lines in HEAD.
+/// * `new`: the lines in the working tree.
+/// * `hunks`: from `diff::hunks(old, new)`.
+/// * `input`: where answers are read from.
+/// * `out`: where hunks and prompts go.
+/// * `painter`:
+fn choose_hunks(
+    old: &[&str],
+    new: &[&str],
+    hunks: &[Hunk],
+    input: &mut impl BufRead,
+    out: &mut impl Write,
+    painter: utils::Painter,
+) -> Result<(Vec<bool>, bool)> {
+    let mut accepted = vec![false; hunks.len()];
+    for (i, hunk) in hunks.iter().enumerate() {
+        print_hunk(old, new, hunk, out, painter)?;
+        match ask(
+            input,
+            out,
+            &format!("Observe this hunk ({}/{})", i + 1, hunks.len()),
+        )? {
+            Answer::Yes => accepted[i] = true,
+            Answer::No => {}
+            Answer::Quit => return Ok((accepted, true)),
+        }
+    }
+    Ok((accepted, false))
+}
+
+/// Prints a hunk, with a few unchanged lines before and after it.
+///
+/// * `old`:
+/// * `new`:
+/// * `hunk`:
+/// * `out`:
+/// * `painter`:
+fn print_hunk(
+    old: &[&str],
+    new: &[&str],
+    hunk: &Hunk,
+    out: &mut impl Write,
+    painter: utils::Painter,
+) -> Result<()> {
+    const CONTEXT: usize = 3;
+    let before = hunk.old.start.saturating_sub(CONTEXT)..hunk.old.start;
+    let after = hunk.old.end..(hunk.old.end + CONTEXT).min(old.len());
+    writeln!(
+        out,
+        "@@ -{},{} +{},{} @@",
+        hunk.old.start + 1,
+        hunk.old.len(),
+        hunk.new.start + 1,
+        hunk.new.len()
+    )?;
+    let line = |prefix: &str, text: &str| format!("{prefix}{}", text.trim_end_matches('\n'));
+    for text in &old[before] {
+        writeln!(out, "{}", line(" ", text))?;
+    }
+    for text in &old[hunk.old.clone()] {
+        writeln!(out, "{}", painter.red(&line("-", text)))?;
+    }
+    for text in &new[hunk.new.clone()] {
+        writeln!(out, "{}", painter.green(&line("+", text)))?;
+    }
+    for text in &old[after] {
+        writeln!(out, "{}", line(" ", text))?;
+    }
+    Ok(())
+}
+
+/// Asks `question` until the answer is one of y, n or q. Running out of input counts as q.
+///
+/// * `input`:
+/// * `out`:
+/// * `question`: without the `[y,n,q]?`.
+fn ask(input: &mut impl BufRead, out: &mut impl Write, question: &str) -> Result<Answer> {
+    let mut buf = String::new();
+    loop {
+        write!(out, "{question} [y,n,q]? ")?;
+        out.flush()?;
+        buf.clear();
+        if input.read_line(&mut buf)? == 0 {
+            writeln!(out)?;
+            return Ok(Answer::Quit);
+        }
+        match buf.trim() {
+            "y" | "Y" => return Ok(Answer::Yes),
+            "n" | "N" => return Ok(Answer::No),
+            "q" | "Q" => return Ok(Answer::Quit),
+            _ => writeln!(
+                out,
+                "y - observe it\nn - skip it\nq - skip it and everything after"
+            )?,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn choose_hunks_test() {
+        let old = ["a\n", "b\n", "c\n", "d\n"];
+        let new = ["a\n", "B\n", "c\n", "d\n", "e"];
+        let hunks = diff::hunks(&old, &new);
+        assert_eq!(hunks.len(), 2);
+        let painter = utils::Painter::new(utils::ColorChoice::Never);
+
+        let mut out = Vec::new();
+        // the first answer isn't valid, so it's asked again.
+        let (accepted, quit) = choose_hunks(
+            &old,
+            &new,
+            &hunks,
+            &mut &b"what\nn\ny\n"[..],
+            &mut out,
+            painter,
+        )
+        .unwrap();
+        assert_eq!((accepted, quit), (vec![false, true], false));
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("@@ -2,1 +2,1 @@\n a\n-b\n+B\n c\n d\n"));
+        assert!(out.contains("y - observe it"));
+        assert!(out.contains("@@ -5,0 +5,1 @@\n b\n c\n d\n+e\n"));
+
+        let picked = diff::apply_hunks(&old, &new, &hunks, &[false, true]);
+        assert_eq!(picked.concat(), "a\nb\nc\nd\ne");
+
+        // quitting, or running out of answers, skips the rest.
+        let (accepted, quit) = choose_hunks(
+            &old,
+            &new,
+            &hunks,
+            &mut &b"y\nq\n"[..],
+            &mut Vec::new(),
+            painter,
+        )
+        .unwrap();
+        assert_eq!((accepted, quit), (vec![true, false], true));
+        let (accepted, quit) =
+            choose_hunks(&old, &new, &hunks, &mut &b""[..], &mut Vec::new(), painter).unwrap();
+        assert_eq!((accepted, quit), (vec![false, false], true));
+    }
+}
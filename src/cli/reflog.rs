@@ -0,0 +1,32 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use gyat::repo::Repository;
+
+use crate::Result;
+
+/// Prints every recorded movement of `HEAD`, newest first.
+///
+/// Each line of `.gyat/HEAD.log` is `<old>\t<new>\t<action>\t<date>`, written by
+/// `utils::update_head`.
+pub fn reflog() -> Result<()> {
+    let repo = Repository::open()?;
+    let gyat_path = &repo.paths().gyat_path;
+    let log = match File::open(gyat_path.join("HEAD.log")) {
+        Ok(f) => f,
+        // nothing has moved HEAD yet.
+        Err(_) => return Ok(()),
+    };
+
+    let lines = BufReader::new(log).lines().collect::<std::io::Result<Vec<_>>>()?;
+    for line in lines.iter().rev() {
+        let parts: Vec<_> = line.split('\t').collect();
+        if parts.len() != 4 {
+            eprintln!("Skipping malformed reflog entry: {line}");
+            continue;
+        }
+        println!("{} -> {}\t{}\t{}", parts[0], parts[1], parts[2], parts[3]);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,144 @@
+use gyat::{fs, hash::Oid, objects, repo::Repository, utils};
+
+use crate::cli::fallback::{compare_trees, process_change};
+use crate::Result;
+
+/// Moves `HEAD` (and the branch it's on) to another commit.
+///
+/// The index is relative to `HEAD`, so it's cleared: nothing is observed on top of the new
+/// `HEAD`. The working tree is left alone, so whatever differs from the new `HEAD` shows up as
+/// unobserved changes in `status`.
+///
+/// * `commit`: the commit to move to, anything `objects::resolve_rev` takes, eg, `HEAD~1`.
+/// * `hard`: also rewrite the working tree to match the commit, throwing away the changes that
+///   aren't tracked. Files that were never tracked are left alone.
+pub fn reset(commit: &str, hard: bool) -> Result<()> {
+    reset_in(&Repository::open()?, commit, hard)
+}
+
+/// `reset`, on `repo` instead of the repository the current directory is in.
+///
+/// * `repo`:
+/// * `commit`:
+/// * `hard`:
+fn reset_in(repo: &Repository, commit: &str, hard: bool) -> Result<()> {
+    let utils::AllPaths {
+        gyat_path,
+        index_path,
+        repo_root,
+        ..
+    } = repo.paths();
+    let target = objects::resolve_rev(repo, commit)?;
+    let target_commit = repo.read_commit(&target)?;
+
+    if hard {
+        let head: Oid = utils::read_head(gyat_path)?
+            .ok_or("There is no commit yet, nothing to reset the working tree from")?
+            .parse()?;
+        let head_root = repo.read_commit(&head)?.root;
+        let mut changes = compare_trees(repo, &head_root, &target_commit.root, &[])?;
+        // those are only what HEAD and the target disagree on, the working tree may not even
+        // match HEAD.
+        let local = fs::compute_changes(repo, std::slice::from_ref(repo_root))?;
+        changes.undo_local(&local, &repo.get_blobs_from_root(&target_commit.root)?);
+        process_change(repo, &changes)?;
+    }
+
+    utils::update_head(gyat_path, &target.to_string(), "reset")?;
+    std::fs::write(index_path, "")?;
+    println!(
+        "HEAD is now at {} {}",
+        &target.to_string()[..7],
+        target_commit.message
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use gyat::{dirtree::Tree, root};
+
+    /// Commits the whole working tree of `repo` on top of `HEAD`.
+    fn commit_all(repo: &Repository, message: &str) -> Oid {
+        let repo_root = &repo.paths().repo_root;
+        let mut tree = Tree::new_in(repo_root).unwrap();
+        tree.add_path(repo_root);
+        let parent = utils::read_head(&repo.paths().gyat_path).unwrap();
+        let commit = repo
+            .write_commit(&format!(
+                "Parent: {}\nTree: {}\nMessage: {message}\nChanges:\n",
+                parent.unwrap_or(String::from("0")),
+                tree.to_object_file().unwrap()
+            ))
+            .unwrap();
+        utils::update_head(&repo.paths().gyat_path, &commit.to_string(), "track").unwrap();
+        commit
+    }
+
+    /// A repository with two commits: `a.txt` alone, then `a.txt` changed and `b.txt` added.
+    fn two_commits() -> (tempfile::TempDir, Repository, Oid, Oid) {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::open_in(&root::init_repo(dir.path()).unwrap()).unwrap();
+        let repo_root = repo.paths().repo_root.clone();
+        std::fs::write(repo_root.join("a.txt"), "one").unwrap();
+        let first = commit_all(&repo, "first");
+        std::fs::write(repo_root.join("a.txt"), "two").unwrap();
+        std::fs::write(repo_root.join("b.txt"), "b").unwrap();
+        let second = commit_all(&repo, "second");
+        (dir, repo, first, second)
+    }
+
+    #[test]
+    fn reset_test() {
+        let (_dir, repo, first, _) = two_commits();
+        let utils::AllPaths {
+            repo_root,
+            gyat_path,
+            index_path,
+            ..
+        } = repo.paths();
+        std::fs::write(index_path, "observed before the reset\n").unwrap();
+
+        reset_in(&repo, "HEAD~1", false).unwrap();
+        assert_eq!(repo.head_commit().unwrap(), Some(first));
+        // the branch moved, HEAD is still on it.
+        let branch = utils::head_target(gyat_path).unwrap();
+        assert_ne!(branch, gyat_path.join("HEAD"));
+        assert_eq!(std::fs::read_to_string(branch).unwrap(), first.to_string());
+        assert_eq!(std::fs::read_to_string(index_path).unwrap(), "");
+        // the working tree is left as it was.
+        assert_eq!(
+            std::fs::read_to_string(repo_root.join("a.txt")).unwrap(),
+            "two"
+        );
+        assert!(repo_root.join("b.txt").exists());
+        let reflog = std::fs::read_to_string(gyat_path.join("HEAD.log")).unwrap();
+        assert!(reflog.lines().last().unwrap().contains("\treset\t"));
+    }
+
+    #[test]
+    fn reset_hard_test() {
+        let (_dir, repo, first, _) = two_commits();
+        let repo_root = &repo.paths().repo_root;
+        let read = |path: &str| std::fs::read_to_string(repo_root.join(path)).ok();
+        std::fs::write(repo_root.join("a.txt"), "edited, not tracked").unwrap();
+        std::fs::write(repo_root.join("untracked.txt"), "mine").unwrap();
+
+        reset_in(&repo, "HEAD~1", true).unwrap();
+        assert_eq!(repo.head_commit().unwrap(), Some(first));
+        assert_eq!(read("a.txt").as_deref(), Some("one"));
+        assert_eq!(read("b.txt"), None);
+        assert_eq!(read("untracked.txt").as_deref(), Some("mine"));
+
+        // HEAD itself throws away edits and brings back deleted files.
+        std::fs::write(repo_root.join("a.txt"), "edited again").unwrap();
+        reset_in(&repo, "HEAD", true).unwrap();
+        assert_eq!(read("a.txt").as_deref(), Some("one"));
+        std::fs::remove_file(repo_root.join("a.txt")).unwrap();
+        reset_in(&repo, "HEAD", true).unwrap();
+        assert_eq!(read("a.txt").as_deref(), Some("one"));
+        assert_eq!(read("untracked.txt").as_deref(), Some("mine"));
+    }
+}
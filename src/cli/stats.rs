@@ -0,0 +1,64 @@
+use gyat::{
+    objects::{self, Reachable},
+    repo::Repository,
+};
+
+use crate::Result;
+
+/// Prints how many objects the repository holds and how much space they take, eg,
+/// `blobs: 420 (3.1 MiB), trees: 88, commits: 12, unreachable: 5`.
+///
+/// Unreachable objects are the ones HEAD can't get to, see `objects::reachable_objects`.
+pub fn stats() -> Result<()> {
+    let repo = Repository::open()?;
+    let store = repo.object_store();
+
+    let reachable = match repo.head_commit()? {
+        Some(head) => objects::reachable_objects(&repo, [head])?,
+        // nothing tracked yet, so nothing is reachable either.
+        None => Reachable::default(),
+    };
+    let blobs = objects::store_usage(store.blobs, &reachable.blobs)?;
+    let trees = objects::store_usage(store.trees, &reachable.trees)?;
+    let commits = objects::store_usage(store.commits, &reachable.commits)?;
+
+    println!(
+        "blobs: {} ({}), trees: {}, commits: {}, unreachable: {}",
+        blobs.count,
+        human_size(blobs.bytes),
+        trees.count,
+        commits.count,
+        blobs.unreachable + trees.unreachable + commits.unreachable
+    );
+
+    Ok(())
+}
+
+/// `bytes` in the largest unit that keeps it at least 1, eg, `3.1 MiB`.
+fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn human_size_test() {
+        assert_eq!(human_size(0), "0 B");
+        assert_eq!(human_size(1023), "1023 B");
+        assert_eq!(human_size(1024), "1.0 KiB");
+        assert_eq!(human_size(3_250_586), "3.1 MiB");
+        assert_eq!(human_size(5 << 40), "5.0 TiB");
+    }
+}
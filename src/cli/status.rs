@@ -0,0 +1,151 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    path::PathBuf,
+};
+
+use gyat::{
+    fs::{self, ChangeType},
+    hash::Oid,
+    objects,
+    repo::Repository,
+    utils,
+};
+
+use crate::Result;
+
+/// What happened to a single path, on both sides of the index.
+#[derive(Default)]
+struct PathStatus {
+    /// HEAD vs the index, ie, what `track` would commit right now.
+    staged: Option<ChangeType>,
+    /// The index vs the working tree, ie, what `observe` hasn't picked up yet.
+    unstaged: Option<ChangeType>,
+    is_dir: bool,
+}
+
+/// Prints what's observed but not tracked yet, and what changed but isn't observed yet.
+///
+/// * `short`: print the porcelain format instead, one `XY path` line per path. `X` is the
+///   observed change, `Y` the unobserved one, each of `A`, `M`, `D` or a space. Empty directories
+///   end with a `/`. Meant for scripts, so it won't change, and paths stay relative to the
+///   repository root wherever it runs from. The long format shows them relative to the current
+///   directory.
+/// * `painter`: observed changes are green, unobserved ones red.
+pub fn status(short: bool, painter: utils::Painter) -> Result<()> {
+    let repo = Repository::open()?;
+    let utils::AllPaths {
+        repo_root,
+        index_path,
+        ..
+    } = repo.paths();
+
+    let head_blobs = match repo.root_tree()? {
+        Some(root) => repo.get_blobs_from_root(&root)?,
+        None => BTreeMap::new(),
+    };
+    let index = fs::read_index(&mut File::open(index_path)?)?;
+
+    // what the working tree is compared against: HEAD, with the observed changes on top.
+    let mut observed_blobs = head_blobs;
+    let mut statuses: BTreeMap<PathBuf, PathStatus> = BTreeMap::new();
+    for entry in index {
+        let status = statuses.entry(entry.path.clone()).or_default();
+        status.staged = Some(entry.change);
+        status.is_dir = entry.hash == objects::EMPTY_DIR;
+        match entry.change {
+            ChangeType::Del => observed_blobs.remove(&entry.path),
+            _ => observed_blobs.insert(entry.path, entry.hash),
+        };
+    }
+
+    let working_blobs: BTreeMap<PathBuf, Oid> =
+        fs::observed_contents(std::slice::from_ref(repo_root), repo_root)?
+            .into_iter()
+            .map(|oc| (oc.path, oc.hash))
+            .collect();
+    for (change, path, old_hash, new_hash) in objects::diff_blobs(&observed_blobs, &working_blobs)
+    {
+        let status = statuses.entry(path).or_default();
+        status.unstaged = Some(change);
+        status.is_dir |= new_hash.or(old_hash) == Some(objects::EMPTY_DIR);
+    }
+
+    if short {
+        for (path, status) in &statuses {
+            println!(
+                "{}{} {}{}",
+                painter.green(short_code(status.staged)),
+                painter.red(short_code(status.unstaged)),
+                path.display(),
+                if status.is_dir { "/" } else { "" }
+            );
+        }
+        return Ok(());
+    }
+
+    if statuses.is_empty() {
+        println!("Nothing to track, working tree clean");
+        return Ok(());
+    }
+    print_section(
+        &repo,
+        &painter.bold("Changes to be tracked:"),
+        statuses.iter().filter_map(|(path, s)| Some((s.staged?, path, s.is_dir))),
+        |line| painter.green(line),
+    );
+    print_section(
+        &repo,
+        &painter.bold("Changes not observed yet:"),
+        statuses.iter().filter_map(|(path, s)| Some((s.unstaged?, path, s.is_dir))),
+        |line| painter.red(line),
+    );
+
+    Ok(())
+}
+
+/// Prints `header` and one line per change under it, or nothing if there's no change.
+///
+/// * `repo`: the paths are shown relative to where it was opened from.
+/// * `header`:
+/// * `changes`: (change, path, whether the path is an empty directory).
+/// * `paint`: colors each line, see `utils::Painter`.
+fn print_section<'a>(
+    repo: &Repository,
+    header: &str,
+    changes: impl Iterator<Item = (ChangeType, &'a PathBuf, bool)>,
+    paint: impl Fn(&str) -> String,
+) {
+    let mut changes = changes.peekable();
+    if changes.peek().is_none() {
+        return;
+    }
+    println!("{header}");
+    for (change, path, is_dir) in changes {
+        let line = format!(
+            "{:<10}{}{}",
+            format!("{}:", long_name(change)),
+            repo.display_path(path).display(),
+            if is_dir { "/" } else { "" }
+        );
+        println!("\t{}", paint(&line));
+    }
+}
+
+/// The one-letter code used by `status --short`.
+fn short_code(change: Option<ChangeType>) -> &'static str {
+    match change {
+        Some(ChangeType::New) => "A",
+        Some(ChangeType::Mod) => "M",
+        Some(ChangeType::Del) => "D",
+        None => " ",
+    }
+}
+
+fn long_name(change: ChangeType) -> &'static str {
+    match change {
+        ChangeType::New => "new",
+        ChangeType::Mod => "modified",
+        ChangeType::Del => "deleted",
+    }
+}
@@ -0,0 +1,57 @@
+use std::fs;
+
+use chrono::Local;
+use gyat::{config::Config, hash::Oid, objects, repo::Repository, utils};
+
+use crate::Result;
+
+/// Tags the commit `HEAD` points to.
+///
+/// Without a message, this is a lightweight tag: `.gyat/refs/tags/<name>` holds the commit hash.
+/// With a message, an annotated tag object is written into `.gyat/tags`, and the ref holds the
+/// hash of that object instead. `objects::resolve_ref` follows either back to the commit.
+///
+/// * `name`: the tag name.
+/// * `message`: the annotation, if any.
+pub fn tag(name: &str, message: &Option<String>) -> Result<()> {
+    if name.is_empty() || name.contains(['/', '\\']) || name == "HEAD" {
+        return Err(format!("Invalid tag name {name}").into());
+    }
+    let repo = Repository::open()?;
+    let utils::AllPaths {
+        gyat_path,
+        refs_path,
+        tags_path,
+        ..
+    } = repo.paths();
+
+    let tag_ref = refs_path.join("tags").join(name);
+    if tag_ref.exists() {
+        return Err(format!("Tag {name} already exists").into());
+    }
+    let head = repo
+        .head_commit()?
+        .ok_or("Nothing to tag, there is no commit yet")?
+        .to_string();
+
+    // repositories created before tags existed don't have these.
+    fs::create_dir_all(refs_path.join("tags"))?;
+    let target = match message {
+        None => head,
+        Some(message) => {
+            fs::create_dir_all(tags_path)?;
+            let config = Config::load(gyat_path)?;
+            let tagger = config.signature();
+            let date = Local::now().format("%a %b %d %H:%M:%S %Y");
+            let tag_content = format!(
+                "Object: {head}\nTag: {name}\nTagger: {tagger}\nDate: {date}\nMessage: {message}\n"
+            );
+            let tag_hash = Oid::of(tag_content.as_bytes());
+            objects::write_object(tags_path, &tag_hash, tag_content.as_bytes())?;
+            tag_hash.to_string()
+        }
+    };
+    fs::write(tag_ref, target)?;
+
+    Ok(())
+}
@@ -1,49 +1,107 @@
-use std::{fmt::Write, fs::File, path::Path};
+use std::{collections::BTreeMap, fmt::Write, fs::File, path::PathBuf};
 
 use super::observe;
 use crate::Result;
 use chrono::{DateTime, Local};
 use gyat::{
+    config::Config,
     dirtree::Tree,
-    fs, hash::{self, get_sha1_string},
+    fs,
     objects::{self, CommitObject},
+    repo::Repository,
     utils,
 };
 
-pub fn track(message: &Option<String>, track_all: bool) -> Result<()> {
+/// Commits the observed changes as a child of `HEAD`.
+///
+/// The message comes from `message`, else from `message_file`, else the commit has no message.
+/// A message that is given cannot be empty, and is joined into one line, see `one_line`.
+///
+/// * `message`: the commit message.
+/// * `message_file`: a file to read the commit message from, like `git commit -F`.
+/// * `track_all`: observe the whole repository first.
+/// * `amend`: replace the `HEAD` commit instead of creating a child of it. The new commit gets
+///   `HEAD`'s parent, and `HEAD`'s message if no message is given. The replaced commit is left
+///   dangling.
+/// * `allow_empty`: commit even if the tree is the same as the parent's, or nothing is observed.
+pub fn track(
+    message: &Option<String>,
+    message_file: &Option<PathBuf>,
+    track_all: bool,
+    amend: bool,
+    allow_empty: bool,
+) -> Result<()> {
+    let action = if amend { "amend" } else { "track" };
+    track_with_action(message, message_file, track_all, amend, allow_empty, action)
+}
+
+/// Same as `track`, but lets the caller name what moved `HEAD` in the reflog.
+///
+/// * `action`: eg, "fallback" when `fallback` tracks the restored working tree.
+pub fn track_with_action(
+    message: &Option<String>,
+    message_file: &Option<PathBuf>,
+    track_all: bool,
+    amend: bool,
+    allow_empty: bool,
+    action: &str,
+) -> Result<()> {
+    let repo = Repository::open()?;
     let utils::AllPaths {
-        head_path,
+        repo_root,
+        gyat_path,
         index_path,
-        commits_path,
+        files_path,
         ..
-    } = utils::gyat_paths()?;
+    } = repo.paths();
+    let head_commit = repo.head_commit()?;
+    if amend && head_commit.is_none() {
+        return Err("Nothing to amend, there is no commit yet".into());
+    }
     if track_all {
-        observe::observe(&[std::path::PathBuf::from(".")])?;
+        // the whole repository, not just where `track` was run from.
+        observe::observe(std::slice::from_ref(repo_root), false, false, false, true)?;
     }
 
-    let observed_list = fs::read_index(&mut File::open(&index_path)?)?;
-    if observed_list.is_empty() {
+    let observed_list = fs::read_index(&mut File::open(index_path)?)?;
+    // amending with nothing observed just rewrites the message. Without a commit to build on,
+    // an empty index has nothing to commit at all.
+    if observed_list.is_empty() && !amend && !(allow_empty && head_commit.is_some()) {
         println!("No changes found");
         return Ok(());
     }
-    let parent_commit = match std::fs::read_to_string(&head_path) {
-        Ok(content) if !content.trim().is_empty() => Some(content.trim().to_string()),
-        _ => None,
+
+    // `observe` stores the blob of everything it stages, so each file goes in as it was observed,
+    // never as it is now. Only empty directories have no blob.
+    let add_entry = |dtree: &mut Tree, entry: &fs::IndexEntry| -> Result<()> {
+        if entry.hash == objects::EMPTY_DIR {
+            dtree.add_path(&entry.path);
+        } else if objects::object_exists(files_path, &entry.hash) {
+            dtree.add_blob(&entry.path, entry.hash);
+        } else {
+            return Err(format!(
+                "{} was observed, but its content isn't stored. Observe it again",
+                repo.display_path(&entry.path).display()
+            )
+            .into());
+        }
+        Ok(())
     };
 
+    // the index is always relative to HEAD, so HEAD's tree is the base even when amending.
     let mut dtree = Tree::new()?;
-    if let Some(pc) = &parent_commit {
-        let pc_hash = hash::from_string(pc).unwrap();
-        let CommitObject { root, .. } = objects::read_commit_content(&pc_hash).unwrap();
-        let mut prev_blobs = objects::get_blobs_from_root(&root)?;
+    let mut amended: Option<CommitObject> = None;
+    if let Some(hc) = &head_commit {
+        let head = repo.read_commit(hc)?;
+        let mut prev_blobs = repo.get_blobs_from_root(&head.root)?;
         for entry in &observed_list {
             use fs::ChangeType::*;
             match entry.change {
                 New => {
-                    dtree.add_path(&entry.path);
+                    add_entry(&mut dtree, entry)?;
                 }
                 Mod => {
-                    dtree.add_path(&entry.path);
+                    add_entry(&mut dtree, entry)?;
                     prev_blobs.remove(&entry.path);
                 }
                 Del => {
@@ -51,36 +109,134 @@ pub fn track(message: &Option<String>, track_all: bool) -> Result<()> {
                 }
             }
         }
-        for blob_left in prev_blobs {
-            dtree.add_path(&blob_left.0);
+        for (path, hash) in prev_blobs {
+            // a directory that used to be empty may have had files observed into it, adding it
+            // again would swallow them.
+            if hash == objects::EMPTY_DIR {
+                if !dtree.contains_path(&path) {
+                    dtree.add_path(&path);
+                }
+                continue;
+            }
+            // what HEAD has, not the working tree, changes that weren't observed stay out.
+            dtree.add_blob(&path, hash);
+        }
+        if amend {
+            amended = Some(head);
         }
     } else {
         for entry in &observed_list {
-            dtree.add_path(&entry.path);
+            add_entry(&mut dtree, entry)?;
         }
     }
+    let parent_commit = match &amended {
+        Some(head) => head.parent,
+        None => head_commit,
+    };
 
     let root_hash = dtree.to_object_file()?;
+    if !allow_empty {
+        let parent_root = match parent_commit {
+            Some(p) => Some(repo.read_commit(&p)?.root),
+            None => None,
+        };
+        if parent_root == Some(root_hash) {
+            return Err(
+                "Nothing to commit, the tree is unchanged. Use --allow-empty to commit anyway"
+                    .into(),
+            );
+        }
+    }
+    // only asked for once we know there's something to commit.
+    let message = match (message, message_file) {
+        (Some(_), Some(_)) => return Err("Use either a message or a message file, not both".into()),
+        (Some(m), None) => Some(m.trim().to_string()),
+        (None, Some(file)) => Some(
+            std::fs::read_to_string(file)
+                .map_err(|e| format!("Cannot read the message file {}: {}", file.display(), e))?
+                .trim()
+                .to_string(),
+        ),
+        // amending keeps the old message.
+        (None, None) => None,
+    };
+    let message = match message {
+        Some(m) if m.is_empty() => return Err("Aborting, the commit message is empty".into()),
+        Some(m) => Some(one_line(&m)),
+        None => None,
+    };
 
     let local_current: DateTime<Local> = Local::now();
     let formatted_date = local_current.format("%a %b %d %H:%M:%S %Y").to_string();
-    let commit_message = message.clone().unwrap_or_default();
-    let formatted_change_list = observed_list.iter().fold(String::new(), |mut out, ie| {
-        let _ = writeln!(out, "{:?}\t{}", ie.change, ie.path.display());
-        out
-    });
+    let commit_message = match (message, &amended) {
+        (None, Some(head)) => head.message.clone(),
+        (message, _) => message.unwrap_or_default(),
+    };
+    let formatted_change_list = match &amended {
+        // the amended commit's own changes need to be kept too, so compare against its parent.
+        Some(head) => {
+            let parent_blobs = match head.parent {
+                Some(p) => repo.get_blobs_from_root(&repo.read_commit(&p)?.root)?,
+                None => BTreeMap::new(),
+            };
+            objects::diff_blobs(&parent_blobs, &repo.get_blobs_from_root(&root_hash)?)
+                .into_iter()
+                .fold(String::new(), |mut out, (change, path, ..)| {
+                    let _ = writeln!(out, "{}\t{}", change.as_token(), path.display());
+                    out
+                })
+        }
+        None => observed_list.iter().fold(String::new(), |mut out, ie| {
+            let _ = writeln!(out, "{}\t{}", ie.change.as_token(), ie.path.display());
+            out
+        }),
+    };
+    // amending doesn't change who wrote the commit.
+    let author = match amended.as_ref().and_then(|head| head.author.clone()) {
+        Some(author) => author,
+        None => Config::load(gyat_path)?.signature(),
+    };
     let commit_content = format!(
-        "Parent: {}\nTree: {}\nMessage: {}\nDate: {}\nChanges:\n{}",
-        parent_commit.unwrap_or(String::from("0")),
-        hash::to_string(&root_hash),
+        "Parent: {}\nTree: {}\nAuthor: {}\nMessage: {}\nDate: {}\nChanges:\n{}",
+        parent_commit.map_or(String::from("0"), |p| p.to_string()),
+        root_hash,
+        author,
         commit_message,
         formatted_date,
         formatted_change_list
     );
-    let commit_hash = get_sha1_string(commit_content.as_bytes());
-    std::fs::write(commits_path.join(Path::new(&commit_hash)), commit_content)?;
-    std::fs::write(head_path, commit_hash)?;
+    let commit_hash = repo.write_commit(&commit_content)?;
+    utils::update_head(gyat_path, &commit_hash.to_string(), action)?;
     std::fs::write(index_path, "")?;
 
     Ok(())
 }
+
+/// A commit keeps its message on the `Message:` line, so a message with several lines is joined
+/// into one, skipping the blank ones.
+///
+/// * `message`:
+fn one_line(message: &str) -> String {
+    message
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn message_test() {
+        assert_eq!(
+            one_line("Fix the parser\n\n  and its tests \n"),
+            "Fix the parser and its tests"
+        );
+        assert_eq!(one_line("\n \n"), "");
+        // nothing special about `#`.
+        assert_eq!(one_line("Close #12"), "Close #12");
+    }
+}
@@ -0,0 +1,168 @@
+//! The repository settings, read from `.gyat/config`.
+//!
+//! The file is a simple INI-ish list of `key = value` lines, optionally grouped under
+//! `[section]` headers. A key under a section is looked up as `section.key`:
+//!
+//! ```text
+//! [user]
+//! name = Huy
+//! email = huy@example.com
+//! [core]
+//! compression = fast
+//! [init]
+//! defaultBranch = main
+//! ```
+
+use std::{fmt::Write, path::Path};
+
+use flate2::Compression;
+
+use crate::Result;
+
+/// All the tunables of a repository.
+///
+/// * `author_name`: `user.name`. Defaults to `$USER`.
+/// * `author_email`: `user.email`. Defaults to empty.
+/// * `compression`: `core.compression`, one of `fast`, `default` or `best`. The `GYAT_COMPRESSION`
+///   environment variable wins over the file.
+/// * `default_branch`: `init.defaultBranch`. Defaults to `main`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    pub author_name: String,
+    pub author_email: String,
+    pub compression: Compression,
+    pub default_branch: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            author_name: std::env::var("USER").unwrap_or(String::from("unknown")),
+            author_email: String::new(),
+            compression: Compression::default(),
+            default_branch: String::from("main"),
+        }
+    }
+}
+
+impl Config {
+    /// `name <email>`, as written in commits and annotated tags.
+    pub fn signature(&self) -> String {
+        format!("{} <{}>", self.author_name, self.author_email)
+    }
+
+    /// Reads `.gyat/config`.
+    ///
+    /// # Return values
+    /// - Err if the file exists but cannot be read or contains an invalid value.
+    /// - Ok(Config) otherwise. If there's no config file, everything is the default.
+    ///
+    /// * `gyat_path`: the `.gyat` directory.
+    pub fn load(gyat_path: &Path) -> Result<Self> {
+        let config_path = gyat_path.join("config");
+        let mut config = if config_path.exists() {
+            Self::parse(&std::fs::read_to_string(config_path)?)?
+        } else {
+            Self::default()
+        };
+        if let Ok(level) = std::env::var("GYAT_COMPRESSION") {
+            config.compression = parse_compression(&level)?;
+        }
+        Ok(config)
+    }
+
+    /// Parses the contents of a config file. Anything not set is the default.
+    /// Unknown keys are ignored.
+    ///
+    /// * `content`:
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut config = Self::default();
+        let mut section = String::new();
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if let Some(s) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                section = s.trim().to_string();
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or(format!("Invalid config line: {line}"))?;
+            let (key, value) = (key.trim(), value.trim());
+            let key = if section.is_empty() {
+                key.to_string()
+            } else {
+                format!("{section}.{key}")
+            };
+            match key.as_str() {
+                "user.name" => config.author_name = value.to_string(),
+                "user.email" => config.author_email = value.to_string(),
+                // bare `compression` is what the config used to look like.
+                "core.compression" | "compression" => {
+                    config.compression = parse_compression(value)?
+                }
+                "init.defaultBranch" => config.default_branch = value.to_string(),
+                _ => {}
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Writes this config into `.gyat/config`, overwriting whatever was there.
+    ///
+    /// * `gyat_path`: the `.gyat` directory.
+    pub fn write(&self, gyat_path: &Path) -> Result<()> {
+        let compression = match self.compression.level() {
+            l if l == Compression::fast().level() => "fast",
+            l if l == Compression::best().level() => "best",
+            _ => "default",
+        };
+        let mut content = String::new();
+        writeln!(content, "[user]")?;
+        writeln!(content, "name = {}", self.author_name)?;
+        writeln!(content, "email = {}", self.author_email)?;
+        writeln!(content, "[core]")?;
+        writeln!(content, "compression = {compression}")?;
+        writeln!(content, "[init]")?;
+        writeln!(content, "defaultBranch = {}", self.default_branch)?;
+        std::fs::write(gyat_path.join("config"), content)?;
+        Ok(())
+    }
+}
+
+/// * `value`: one of `fast`, `default` or `best`.
+fn parse_compression(value: &str) -> Result<Compression> {
+    match value {
+        "default" => Ok(Compression::default()),
+        "fast" => Ok(Compression::fast()),
+        "best" => Ok(Compression::best()),
+        v => Err(format!("Invalid compression level {v}, expected fast, default or best").into()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_test() {
+        let config = Config::parse(
+            "[user]\nname = Huy\nemail = huy@example.com\n\n# comment\n[core]\ncompression = best\n[init]\ndefaultBranch = trunk\n",
+        )
+        .unwrap();
+        assert_eq!(config.author_name, "Huy");
+        assert_eq!(config.author_email, "huy@example.com");
+        assert_eq!(config.compression, Compression::best());
+        assert_eq!(config.default_branch, "trunk");
+
+        // anything missing is the default.
+        let config = Config::parse("compression = fast\n").unwrap();
+        assert_eq!(config.compression, Compression::fast());
+        assert_eq!(config.default_branch, "main");
+
+        assert!(Config::parse("[core]\ncompression = bogus\n").is_err());
+        assert!(Config::parse("no equal sign\n").is_err());
+    }
+}
@@ -0,0 +1,168 @@
+//! Line-level diffing, for when knowing that a blob changed isn't enough.
+
+use std::ops::Range;
+
+/// Finds which lines of `new` were kept from `old`, along the longest common subsequence of the
+/// two.
+///
+/// This is the plain dynamic programming LCS, so it's quadratic in the number of lines that
+/// differ. The common prefix and suffix are matched up front, so small edits to big files stay
+/// cheap.
+///
+/// # Return value
+/// One entry per line of `new`: Some(index of the same line in `old`) if the line was kept, None
+/// if it was added.
+///
+/// * `old`: the lines before.
+/// * `new`: the lines after.
+pub fn matching_lines<T: PartialEq>(old: &[T], new: &[T]) -> Vec<Option<usize>> {
+    let prefix = old.iter().zip(new).take_while(|(o, n)| o == n).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(o, n)| o == n)
+        .count();
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+
+    // lcs[i][j] is the length of the LCS of old_mid[i..] and new_mid[j..].
+    let width = new_mid.len() + 1;
+    let mut lcs = vec![0u32; (old_mid.len() + 1) * width];
+    for i in (0..old_mid.len()).rev() {
+        for j in (0..new_mid.len()).rev() {
+            lcs[i * width + j] = if old_mid[i] == new_mid[j] {
+                lcs[(i + 1) * width + j + 1] + 1
+            } else {
+                lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut ret: Vec<Option<usize>> = (0..prefix).map(Some).collect();
+    let (mut i, mut j) = (0, 0);
+    while j < new_mid.len() {
+        if i < old_mid.len() && old_mid[i] == new_mid[j] {
+            ret.push(Some(prefix + i));
+            i += 1;
+            j += 1;
+        } else if i < old_mid.len() && lcs[(i + 1) * width + j] >= lcs[i * width + j + 1] {
+            // old_mid[i] was removed.
+            i += 1;
+        } else {
+            ret.push(None);
+            j += 1;
+        }
+    }
+    ret.extend((old.len() - suffix..old.len()).map(Some));
+    ret
+}
+
+/// A run of lines that changed together: `old` was replaced by `new`. Either can be empty, for
+/// pure additions or removals.
+///
+/// * `old`: the lines replaced, as indices into the old version.
+/// * `new`: the lines replacing them, as indices into the new version.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    pub old: Range<usize>,
+    pub new: Range<usize>,
+}
+
+/// Splits the difference between `old` and `new` into hunks, see `matching_lines`.
+///
+/// # Return value
+/// The hunks, in order. Empty if the two are the same.
+///
+/// * `old`:
+/// * `new`:
+pub fn hunks<T: PartialEq>(old: &[T], new: &[T]) -> Vec<Hunk> {
+    let mut ret = Vec::new();
+    let (mut old_start, mut new_start) = (0, 0);
+    // the end of both versions counts as a kept line, so the last hunk gets closed too.
+    let kept = matching_lines(old, new)
+        .into_iter()
+        .enumerate()
+        .filter_map(|(n, o)| Some((o?, n)))
+        .chain(std::iter::once((old.len(), new.len())));
+    for (o, n) in kept {
+        if old_start < o || new_start < n {
+            ret.push(Hunk {
+                old: old_start..o,
+                new: new_start..n,
+            });
+        }
+        (old_start, new_start) = (o + 1, n + 1);
+    }
+    ret
+}
+
+/// Rebuilds a version of the lines with only some of the hunks applied to `old`.
+///
+/// * `old`: the lines before.
+/// * `new`: the lines after.
+/// * `hunks`: from `hunks(old, new)`.
+/// * `accepted`: one per hunk, whether it's applied.
+pub fn apply_hunks<T: Clone>(old: &[T], new: &[T], hunks: &[Hunk], accepted: &[bool]) -> Vec<T> {
+    debug_assert_eq!(hunks.len(), accepted.len());
+    let mut ret = Vec::with_capacity(old.len().max(new.len()));
+    let mut old_pos = 0;
+    for (hunk, accepted) in hunks.iter().zip(accepted) {
+        ret.extend_from_slice(&old[old_pos..hunk.old.start]);
+        if *accepted {
+            ret.extend_from_slice(&new[hunk.new.clone()]);
+        } else {
+            ret.extend_from_slice(&old[hunk.old.clone()]);
+        }
+        old_pos = hunk.old.end;
+    }
+    ret.extend_from_slice(&old[old_pos..]);
+    ret
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn matching_lines_test() {
+        let old = ["a", "b", "c", "d", "e"];
+        // removed "b", changed "d", added "f".
+        let new = ["a", "c", "D", "e", "f"];
+        assert_eq!(
+            matching_lines(&old, &new),
+            [Some(0), Some(2), None, Some(4), None]
+        );
+
+        assert_eq!(matching_lines(&old, &old), [0, 1, 2, 3, 4].map(Some));
+        assert_eq!(matching_lines(&[], &["a"]), [None]);
+        assert!(matching_lines(&old, &[]).is_empty());
+        // a line moved to the front only keeps one of the two orders.
+        assert_eq!(
+            matching_lines(&["a", "b", "c"], &["c", "a", "b"]),
+            [None, Some(0), Some(1)]
+        );
+    }
+
+    #[test]
+    fn hunks_test() {
+        let old = ["a", "b", "c", "d", "e"];
+        let new = ["a", "c", "D", "e", "f"];
+        let hunk = |old, new| Hunk { old, new };
+        let hunks = hunks(&old, &new);
+        assert_eq!(
+            hunks,
+            [hunk(1..2, 1..1), hunk(3..4, 2..3), hunk(5..5, 4..5)]
+        );
+
+        assert_eq!(apply_hunks(&old, &new, &hunks, &[true, true, true]), new);
+        assert_eq!(apply_hunks(&old, &new, &hunks, &[false, false, false]), old);
+        assert_eq!(
+            apply_hunks(&old, &new, &hunks, &[false, true, false]),
+            ["a", "b", "c", "D", "e"]
+        );
+
+        assert!(super::hunks(&old, &old).is_empty());
+        assert_eq!(super::hunks(&[], &["a"]), [hunk(0..0, 0..1)]);
+    }
+}
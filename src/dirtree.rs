@@ -1,8 +1,10 @@
 use crate::{
-    hash, objects,
-    utils::{gyat_paths, AllPaths},
+    config::Config,
+    hash::Oid,
+    objects::{self, FType, FileObjectRef},
     Result,
 };
+use flate2::Compression;
 
 // There are some nice quirks I will abuse to make my life easier:
 // 1. If I removed a node, I would want to remove all its children anyways.
@@ -27,10 +29,11 @@ use crate::{
 // As of writing this update, everything leaf in `dirtree` is expected to represent file/blob.
 
 use std::{
-    cmp::Reverse, collections::{BinaryHeap, HashMap}, ffi::{OsStr, OsString}, fs::{self, File}, io::{Seek, SeekFrom}, path::{Component, Path, PathBuf}
+    cmp::Reverse, collections::{BinaryHeap, HashMap}, ffi::{OsStr, OsString}, fs::File, path::{Component, Path, PathBuf}
 };
 
 use crate::root;
+use rayon::prelude::*;
 
 // not very cache-line-efficient since it's a big chongus, but anyways.
 // You never expect any tree data structure to be cache efficient in the first place.
@@ -43,6 +46,8 @@ use crate::root;
 /// * `filename`: name of the file/directory this node represents.
 ///   Should have been named `component` to be honest, 'cuz that's actually what it is.
 /// * `parent`: the parent of this node.
+/// * `blob`: for a leaf, a blob that's already stored and goes in instead of the file in the
+///   working tree. See `Tree::add_blob`.
 ///
 /// # Notes
 /// * Any function taking in a `usize` as parent/child only makes sense in the context of the `Tree`
@@ -53,6 +58,7 @@ pub struct TreeNode {
     // filename.
     filename: OsString,
     parent: Option<usize>,
+    blob: Option<Oid>,
 }
 
 #[derive(Debug, Clone)]
@@ -68,6 +74,8 @@ pub struct Tree {
     next_frees: BinaryHeap<Reverse<usize>>,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// What a node of the `Tree` turns into once written as an object.
 pub enum ObjectType {
     Blob,
     Tree,
@@ -85,6 +93,7 @@ impl TreeNode {
             children: HashMap::new(),
             filename: filename.to_owned(),
             parent: None,
+            blob: None,
         }
     }
 
@@ -152,10 +161,26 @@ impl TreeNode {
 }
 
 impl Tree {
+    /// A tree for the repository the current working directory is in.
     pub fn new() -> Result<Self> {
-        Ok(Self {
-            repo_root: root::get_repo_root(Path::new("."))
+        Self::new_in(
+            &root::get_repo_root(Path::new("."))
                 .ok_or("The current working directory is not in any repository")?,
+        )
+    }
+
+    /// A tree for the repository at `repo_root`, wherever the current working directory is.
+    ///
+    /// # Return values
+    /// - Err if `repo_root` doesn't have a `.gyat` directory right inside it.
+    ///
+    /// * `repo_root`:
+    pub fn new_in(repo_root: &Path) -> Result<Self> {
+        if !repo_root.join(".gyat").is_dir() {
+            return Err(format!("{} is not a gyat repository root", repo_root.display()).into());
+        }
+        Ok(Self {
+            repo_root: repo_root.canonicalize()?,
             nodes: vec![TreeNode::new(Path::new(".").as_os_str())],
             size: 1,
             next_frees: BinaryHeap::new(),
@@ -169,26 +194,34 @@ impl Tree {
             .map(|n| self.relative_path(n))
     }
 
+    /// Like `leaves`, but yields every valid node (except the repository root) along with what
+    /// kind of object it becomes. Leaves are blobs, anything else is a tree.
+    pub fn entries(&self) -> impl Iterator<Item = (PathBuf, ObjectType)> + '_ {
+        self.nodes
+            .iter()
+            .skip(1)
+            .filter(|n| n.is_valid())
+            .map(|n| {
+                let otype = if n.is_leaf() {
+                    ObjectType::Blob
+                } else {
+                    ObjectType::Tree
+                };
+                (self.relative_path(n), otype)
+            })
+    }
+
     /// Traverses down the tree starting from the root to see if the path in question exists.
     ///
-    /// * `path`:
+    /// * `path`: relative to the repository root (not the current directory), or absolute.
     pub fn contains_path(&self, path: &Path) -> bool {
-        if let Some(p) = root::get_repo_root(path) {
-            if p != self.repo_root {
-                return false;
-            }
-        }
+        let Some(path) = self.repo_relative(path) else {
+            return false;
+        };
         if self.only_repo_root() {
             return false;
         }
 
-        let path = if !path.is_absolute() {
-            path
-        } else {
-            path.strip_prefix(root::get_repo_root(path).unwrap())
-                .unwrap()
-        };
-
         let mut idx = 0;
         for comp in path
             .components()
@@ -202,8 +235,9 @@ impl Tree {
             .filter(|cp| !matches!(cp, Component::CurDir))
             .map(|c| c.as_os_str())
         {
+            // a leaf is a file, so there can't be anything left under it.
             if self.nodes[idx].is_leaf() {
-                return true;
+                return false;
             }
             match self.nodes[idx].get_component(comp) {
                 None => return false,
@@ -217,54 +251,72 @@ impl Tree {
     ///
     /// * `path`:
     pub fn remove_path(&self, path: &Path) -> bool {
-        if let Some(p) = root::get_repo_root(path) {
-            if p != self.repo_root {
-                return false;
-            }
-        }
         if self.only_repo_root() {
             return false;
         }
 
         // TODO: maybe I will allow removal of elements from the dirtree.
         #[allow(unused_variables)]
-        let path = if !path.is_absolute() {
-            path
-        } else {
-            path.strip_prefix(root::get_repo_root(path).unwrap())
-                .unwrap()
+        let Some(path) = self.repo_relative(path) else {
+            return false;
         };
 
         false
     }
 
+    /// * `path`: relative to the repository root (not the current directory), or absolute. Must
+    ///   exist.
     pub fn add_path(&mut self, path: &Path) -> bool {
-        if !path.exists() {
+        let Some(path) = self.repo_relative(path) else {
             return false;
-        }
-        if let Some(r) = root::get_repo_root(path) {
-            if r != self.repo_root {
-                return false;
-            }
-        } else {
+        };
+        if !self.repo_root.join(&path).exists() {
             return false;
         }
         // if the repo root is/was added, anything else is ignored.
         if self.only_repo_root() {
             return false;
         }
-        if path
-            .canonicalize()
-            .unwrap()
-            .strip_prefix(&self.repo_root)
-            .unwrap()
-            .as_os_str()
-            .is_empty()
-        {
+        if path.as_os_str().is_empty() {
             self.size = 0;
             return true;
         }
 
+        self.insert(&path).is_some_and(|(added, _)| added)
+    }
+
+    /// Like `add_path`, but the leaf at `path` becomes `blob` instead of whatever is in the working
+    /// tree, which doesn't even need to exist. For content that was stored on its own, eg, a file
+    /// as it was when observed, or with only some of its changes observed.
+    ///
+    /// * `path`: relative to the repository root (not the current directory), or absolute. Can't
+    ///   be the repository root.
+    /// * `blob`: must already be stored.
+    pub fn add_blob(&mut self, path: &Path, blob: Oid) -> bool {
+        let Some(path) = self.repo_relative(path) else {
+            return false;
+        };
+        if self.only_repo_root() || path.as_os_str().is_empty() {
+            return false;
+        }
+        match self.insert(&path) {
+            Some((_, idx)) => {
+                self.nodes[idx].blob = Some(blob);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Adds the nodes for `path`, or finds them if they're already there. Anything that was
+    /// under the last one is dropped, it's a leaf now.
+    ///
+    /// # Return value
+    /// - None if a leaf is in the way, ie, `path` is under a file.
+    /// - Some((whether any node was added, the index of the last one)) otherwise.
+    ///
+    /// * `path`: relative to the repository root, not empty.
+    fn insert(&mut self, path: &Path) -> Option<(bool, usize)> {
         let mut idx = 0;
         let mut added = false;
         for comp in path
@@ -279,14 +331,14 @@ impl Tree {
             match self.nodes[idx].get_component(comp) {
                 // I will try to find a way to reduce the nesting level. This looks awful.
                 None => {
-                    match self.next_frees.pop() {
+                    let new_idx = match self.next_frees.pop() {
                         None => {
                             self.nodes.push({
                                 let mut ret = TreeNode::new(comp);
                                 ret.add_parent(idx);
                                 ret
                             });
-                            self.nodes[idx].add_child(comp, self.size);
+                            self.nodes.len() - 1
                         }
                         Some(Reverse(s)) => {
                             self.nodes[s] = {
@@ -294,10 +346,12 @@ impl Tree {
                                 ret.add_parent(idx);
                                 ret
                             };
-                            self.nodes[idx].add_child(comp, s);
+                            s
                         }
                     };
-                    idx = self.size;
+                    self.nodes[idx].add_child(comp, new_idx);
+                    // a reused slot isn't at the end, so `self.size` isn't the new node.
+                    idx = new_idx;
                     self.size += 1;
                     added = true;
                     continue;
@@ -307,7 +361,7 @@ impl Tree {
                 }
             }
             if self.nodes[idx].is_leaf() {
-                return false;
+                return None;
             }
         }
         // fuck you borrow-checker.
@@ -318,7 +372,19 @@ impl Tree {
         }
         self.size -= self.nodes[idx].remove_children();
 
-        added
+        Some((added, idx))
+    }
+
+    /// `path` relative to the repository root, or None if it's outside the repository.
+    /// Relative paths are already taken as relative to the repository root, so the current
+    /// directory doesn't matter.
+    ///
+    /// * `path`:
+    fn repo_relative(&self, path: &Path) -> Option<PathBuf> {
+        crate::fs::normalize(&self.repo_root.join(path))
+            .strip_prefix(&self.repo_root)
+            .ok()
+            .map(Path::to_path_buf)
     }
 
     fn only_repo_root(&self) -> bool {
@@ -345,91 +411,158 @@ impl Tree {
     /// # Return values
     /// - Err for any I/O error.
     /// - Ok([u8;20]) otherwise. This is the SHA1 in bytes of the repository root tree.
-    pub fn to_object_file(&self) -> Result<[u8; 20]> {
-        self.to_object_file_recursive(&self.nodes[0])
+    pub fn to_object_file(&self) -> Result<Oid> {
+        if self.only_repo_root() {
+            return self.expand_repo_root()?.to_object_file();
+        }
+        let level = self.compression_level()?;
+        Ok(self
+            .to_object_file_recursive(&self.nodes[0], level, true)?
+            .1)
+    }
+
+    #[cfg(test)]
+    /// Same as `to_object_file`, but hashes one child at a time.
+    fn to_object_file_serial(&self) -> Result<Oid> {
+        if self.only_repo_root() {
+            return self.expand_repo_root()?.to_object_file_serial();
+        }
+        let level = self.compression_level()?;
+        Ok(self
+            .to_object_file_recursive(&self.nodes[0], level, false)?
+            .1)
+    }
+
+    /// The blob compression level of this tree's repository, see `objects::compression_level`.
+    fn compression_level(&self) -> Result<Compression> {
+        Ok(Config::load(&self.repo_root.join(".gyat"))?.compression)
+    }
+
+    /// Once the repository root is added, the tree only keeps the `size = 0` sentinel and no
+    /// nodes. This builds the tree that sentinel stands for, with every file and empty directory
+    /// in the repository that `observe .` would pick up, ie, not `.gyat` or anything ignored.
+    fn expand_repo_root(&self) -> Result<Tree> {
+        let mut tree = Tree::new_in(&self.repo_root)?;
+        let is_ignored = crate::fs::ignore_matcher(&self.repo_root)?;
+        let paths = crate::fs::get_files_and_syms(&self.repo_root)?
+            .into_iter()
+            .chain(crate::fs::get_empty_dirs(&self.repo_root)?);
+        for path in paths {
+            let root_relative = path.strip_prefix(&self.repo_root)?;
+            if root_relative.as_os_str().is_empty() || is_ignored(root_relative) {
+                continue;
+            }
+            tree.add_path(root_relative);
+        }
+        Ok(tree)
     }
 
     /// Recursive call for `to_object_file`.
     ///
     /// # Return values
     /// - Err for any I/O error.
-    /// - Ok([u8;20]) otherwise. This is the SHA1 in bytes of the object represented by the node
-    ///   passed in.
+    /// - Ok((FType, [u8;20])) otherwise. This is the type and the SHA1 in bytes of the object
+    ///   represented by the node passed in.
     ///
     /// * `node`:
-    fn to_object_file_recursive(&self, node: &TreeNode) -> Result<[u8; 20]> {
-        let AllPaths {
-            dirs_path,
-            files_path,
-            ..
-        } = gyat_paths()?;
-
-        let source_path = self.relative_path(node);
-        let mut source_file = File::open(&source_path)?;
-        if node.is_leaf() {
-            let hash = hash::digest_file(&mut source_file)?;
-            source_file.seek(SeekFrom::Start(0))?;
-            let blob_content = objects::format_blob_content(&mut source_file)?;
-
-            let blob_path = files_path.join(Path::new(&hash::to_string(&hash)));
-            if !blob_path.exists() {
-                fs::write(blob_path, blob_content)?;
+    /// * `level`: the compression level of new blobs.
+    /// * `parallel`: whether the children of `node` are hashed in parallel.
+    fn to_object_file_recursive(
+        &self,
+        node: &TreeNode,
+        level: Compression,
+        parallel: bool,
+    ) -> Result<(FType, Oid)> {
+        // everything is relative to this tree's repository, the current directory doesn't matter.
+        let gyat_path = self.repo_root.join(".gyat");
+        let (dirs_path, files_path) = (gyat_path.join("dirs"), gyat_path.join("files"));
+
+        if let Some(blob) = node.blob.filter(|_| node.is_leaf()) {
+            return Ok((FType::Blob, blob));
+        }
+        let source_path = self.repo_root.join(self.relative_path(node));
+        if node.is_leaf() && source_path.is_dir() {
+            // the only directories that end up as leaves are empty ones.
+            if !objects::object_exists(&dirs_path, &objects::EMPTY_TREE) {
+                objects::write_object(&dirs_path, &objects::EMPTY_TREE, b"")?;
             }
-            return Ok(hash);
+            return Ok((FType::Tree, objects::EMPTY_TREE));
         }
-
-        let mut tree_content = String::new();
-        for child in &node.children {
-            let hash = self.to_object_file_recursive(&self.nodes[*child.1])?;
-            let child_type = if self.nodes[*child.1].is_leaf() {
-                "blob"
-            } else {
-                "tree"
-            };
-            tree_content.push_str(&format!(
-                "{}\t{}\t{}\n",
-                child_type,
-                hash::to_string(&hash),
-                Path::new(&self.nodes[*child.1].filename).display()
-            ));
+        if node.is_leaf() {
+            let mut source_file = File::open(&source_path)?;
+            let hash = objects::write_file_blob(&files_path, &mut source_file, level)?;
+            return Ok((FType::Blob, hash));
         }
-        let tree_hash = hash::get_sha1_bytes(tree_content.as_bytes());
-        let tree_path = dirs_path.join(Path::new(&hash::to_string(&tree_hash)));
 
-        if !tree_path.exists() {
-            fs::write(&tree_path, tree_content)?;
+        // The order children get hashed in doesn't matter, `format_tree_content` sorts them
+        // before writing.
+        let children: Vec<usize> = node.children.values().copied().collect();
+        // Box<dyn Error> isn't Send, so the errors are stringified to cross threads.
+        let hash_child = |child: &usize| {
+            self.to_object_file_recursive(&self.nodes[*child], level, parallel)
+                .map_err(|e| e.to_string())
+        };
+        let hashes: Vec<(FType, Oid)> = if parallel {
+            children
+                .par_iter()
+                .map(hash_child)
+                .collect::<std::result::Result<_, _>>()?
+        } else {
+            children
+                .iter()
+                .map(hash_child)
+                .collect::<std::result::Result<_, _>>()?
+        };
+
+        let tree_content = objects::format_tree_content(children.iter().zip(&hashes).map(
+            |(child, (ftype, hash))| FileObjectRef {
+                ftype: *ftype,
+                hash,
+                component: &self.nodes[*child].filename,
+            },
+        ));
+        let tree_hash = Oid::of(&tree_content);
+        if !objects::object_exists(&dirs_path, &tree_hash) {
+            objects::write_object(&dirs_path, &tree_hash, &tree_content)?;
         }
 
-        Ok(tree_hash)
+        Ok((FType::Tree, tree_hash))
     }
 }
 
 #[cfg(test)]
 mod test {
-    use std::env::current_dir;
-
     use clap::builder::OsStr;
 
     use super::*;
+    use crate::{hash, utils::temp_repo};
+    use std::fs;
+
+    /// Creates `files` (and their parent directories) inside `repo_root`.
+    fn touch(repo_root: &Path, files: &[&str]) {
+        for file in files {
+            let path = repo_root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, file).unwrap();
+        }
+    }
 
     #[test]
     fn init_test() {
-        debug_assert!(
-            root::is_repo(Path::new(".")),
-            "Please run this test inside a .gyat repo"
-        );
-        let tree = Tree::new().expect("Please run this test inside a .gyat repo");
+        let (_dir, repo_root) = temp_repo();
+        let tree = Tree::new_in(&repo_root).unwrap();
         assert_eq!(tree.size, 1);
         assert_eq!(&tree.nodes[0], &TreeNode::new(&OsStr::from(".")));
+        // not a repository root, even though it's inside one.
+        touch(&repo_root, &["src/cli.rs"]);
+        assert!(Tree::new_in(&repo_root.join("src")).is_err());
     }
 
     #[test]
     fn add_root_test() {
-        debug_assert!(
-            root::is_repo(Path::new(".")),
-            "Please run this test inside a .gyat repo"
-        );
-        let mut tree = Tree::new().expect("Please run this test inside a .gyat repo");
+        let (_dir, repo_root) = temp_repo();
+        touch(&repo_root, &["src/cli.rs"]);
+        let mut tree = Tree::new_in(&repo_root).unwrap();
         assert!(!tree.only_repo_root());
         assert!(tree.add_path(Path::new(".")));
         assert!(tree.only_repo_root());
@@ -438,48 +571,213 @@ mod test {
 
     #[test]
     fn add_test_1() {
-        debug_assert!(
-            root::is_repo(Path::new(".")),
-            "Please run this test inside a .gyat repo"
-        );
-        let mut tree = Tree::new().expect("Please run this test inside a .gyat repo");
+        let (_dir, repo_root) = temp_repo();
+        touch(&repo_root, &["src/cli.rs", "test-data/cargo-mimic.txt"]);
+        let mut tree = Tree::new_in(&repo_root).unwrap();
         assert!(tree.add_path(Path::new("src")));
         assert!(tree.contains_path(Path::new("src")));
         assert!(tree.add_path(Path::new("test-data")));
         assert!(tree.contains_path(Path::new("test-data")));
         assert!(!tree.add_path(Path::new("src/cli.rs")));
+        assert!(!tree.add_path(Path::new("does-not-exist")));
         assert!(tree.add_path(Path::new(".")));
         assert!(!tree.contains_path(Path::new("src")));
     }
 
     #[test]
     fn add_test_2() {
-        debug_assert!(
-            root::is_repo(Path::new(".")),
-            "Please run this test inside a .gyat repo"
-        );
-        let mut tree = Tree::new().expect("Please run this test inside a .gyat repo");
+        let (_dir, repo_root) = temp_repo();
+        touch(&repo_root, &["src/cli.rs"]);
+        let mut tree = Tree::new_in(&repo_root).unwrap();
         assert!(tree.add_path(Path::new("src/cli.rs")));
         assert!(!tree.add_path(Path::new("src")));
-        assert!(tree.contains_path(Path::new("src/cli.rs")));
+        // `src` swallowed `src/cli.rs` and is now a leaf itself.
+        assert!(!tree.contains_path(Path::new("src/cli.rs")));
         assert!(tree.contains_path(Path::new("src")));
         // I forgot to test absolute path, so here you go.
-        assert!(tree.contains_path(&Path::join(&current_dir().unwrap(), "src")));
+        assert!(tree.contains_path(&repo_root.join("src")));
+        // nothing outside the repository is in it.
+        assert!(!tree.contains_path(Path::new("../src")));
+        assert!(!tree.add_path(repo_root.parent().unwrap()));
+    }
+
+    #[test]
+    fn contains_past_leaf_test() {
+        let (_dir, repo_root) = temp_repo();
+        touch(&repo_root, &["src/cli.rs"]);
+        let mut tree = Tree::new_in(&repo_root).unwrap();
+        assert!(tree.add_path(Path::new("src/cli.rs")));
+        assert!(tree.contains_path(Path::new("src/cli.rs")));
+        // `src/cli.rs` is a leaf, anything under it is nonsense.
+        assert!(!tree.contains_path(Path::new("src/cli.rs/extra")));
+        assert!(!tree.contains_path(Path::new("src/cli.rs/extra/more")));
     }
 
     #[test]
     fn leaves_test() {
-        debug_assert!(
-            root::is_repo(Path::new(".")),
-            "Please run this test inside a .gyat repo"
+        let (_dir, repo_root) = temp_repo();
+        touch(
+            &repo_root,
+            &["src/cli.rs", "src/hash.rs", "test-data/cargo-mimic.txt"],
+        );
+        let mut tree = Tree::new_in(&repo_root).unwrap();
+        assert!(tree.add_path(Path::new("src/cli.rs")));
+        assert!(tree.add_path(Path::new("src/hash.rs")));
+        assert!(tree.add_path(Path::new("test-data")));
+        let mut leaves: Vec<PathBuf> = tree.leaves().collect();
+        leaves.sort();
+        assert_eq!(
+            leaves,
+            [
+                Path::new("src/cli.rs"),
+                Path::new("src/hash.rs"),
+                Path::new("test-data")
+            ]
+        );
+    }
+
+    #[test]
+    fn entries_test() {
+        let (_dir, repo_root) = temp_repo();
+        touch(
+            &repo_root,
+            &["src/cli.rs", "src/hash.rs", "test-data/cargo-mimic.txt"],
         );
-        let mut tree = Tree::new().expect("Please run this test inside a .gyat repo");
+        let mut tree = Tree::new_in(&repo_root).unwrap();
         assert!(tree.add_path(Path::new("src/cli.rs")));
         assert!(tree.add_path(Path::new("src/hash.rs")));
         assert!(tree.add_path(Path::new("test-data")));
-        // println!("{:#?}", tree.nodes);
-        for leaf in tree.leaves() {
-            println!("{}", leaf.display());
+        let entries: HashMap<PathBuf, ObjectType> = tree.entries().collect();
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[Path::new("src")], ObjectType::Tree);
+        assert_eq!(entries[Path::new("src/cli.rs")], ObjectType::Blob);
+        assert_eq!(entries[Path::new("src/hash.rs")], ObjectType::Blob);
+        assert_eq!(entries[Path::new("test-data")], ObjectType::Blob);
+    }
+
+    #[test]
+    fn expand_repo_root_test() {
+        let (_dir, repo_root) = temp_repo();
+        touch(
+            &repo_root,
+            &[
+                "src/dirtree.rs",
+                "test-data/cargo-mimic.txt",
+                "target/debug/gyat",
+                "notes.tmp",
+            ],
+        );
+        fs::create_dir(repo_root.join("empty")).unwrap();
+        fs::create_dir(repo_root.join("ignored-empty")).unwrap();
+        // the same ignores as `observe`.
+        fs::write(repo_root.join(".gyatignore"), "^target\n^ignored-empty$\n").unwrap();
+        fs::write(repo_root.join(".gyat/info/exclude"), "\\.tmp$\n").unwrap();
+        let mut tree = Tree::new_in(&repo_root).unwrap();
+        assert!(tree.add_path(Path::new(".")));
+        let expanded = tree.expand_repo_root().unwrap();
+        assert!(!expanded.only_repo_root());
+        let mut leaves: Vec<PathBuf> = expanded.leaves().collect();
+        leaves.sort();
+        assert_eq!(
+            leaves,
+            [
+                Path::new(".gyatignore"),
+                Path::new("empty"),
+                Path::new("src/dirtree.rs"),
+                Path::new("test-data/cargo-mimic.txt")
+            ]
+        );
+    }
+
+    #[test]
+    fn parallel_hash_test() {
+        let (_dir, repo_root) = temp_repo();
+        let files: Vec<String> = (0..50)
+            .map(|i| format!("dir{}/file{i}.txt", i % 7))
+            .collect();
+        touch(
+            &repo_root,
+            &files.iter().map(String::as_str).collect::<Vec<_>>(),
+        );
+        let mut tree = Tree::new_in(&repo_root).unwrap();
+        for file in &files {
+            assert!(tree.add_path(Path::new(file)));
         }
+        assert_eq!(
+            tree.to_object_file().unwrap(),
+            tree.to_object_file_serial().unwrap()
+        );
+    }
+
+    #[test]
+    fn deterministic_hash_test() {
+        let (_dir, repo_root) = temp_repo();
+        let mut files = vec![
+            "src/cli.rs",
+            "src/cli/create.rs",
+            "src/hash.rs",
+            "README.md",
+        ];
+        touch(&repo_root, &files);
+        let mut first = Tree::new_in(&repo_root).unwrap();
+        for file in &files {
+            assert!(first.add_path(Path::new(file)));
+        }
+        // same files, different insertion order.
+        files.reverse();
+        let mut second = Tree::new_in(&repo_root).unwrap();
+        for file in &files {
+            assert!(second.add_path(Path::new(file)));
+        }
+        assert_eq!(
+            first.to_object_file().unwrap(),
+            second.to_object_file().unwrap()
+        );
+    }
+
+    #[test]
+    fn objects_in_own_repo_test() {
+        let (_dir, repo_root) = temp_repo();
+        touch(&repo_root, &["src/cli.rs"]);
+        fs::create_dir(repo_root.join("empty")).unwrap();
+        let mut tree = Tree::new_in(&repo_root).unwrap();
+        assert!(tree.add_path(Path::new("src/cli.rs")));
+        assert!(tree.add_path(Path::new("empty")));
+        // the tests don't run inside `repo_root`, so everything must be found through it.
+        let root_hash = tree.to_object_file().unwrap();
+
+        let gyat_path = repo_root.join(".gyat");
+        let dirs_path = gyat_path.join("dirs");
+        let blob_hash =
+            hash::digest_file(&mut File::open(repo_root.join("src/cli.rs")).unwrap()).unwrap();
+        assert!(dirs_path.join(root_hash.to_string()).is_file());
+        assert!(dirs_path.join(objects::EMPTY_TREE.to_string()).is_file());
+        assert!(gyat_path
+            .join("files")
+            .join(blob_hash.to_string())
+            .is_file());
+        let root_content = fs::read_to_string(dirs_path.join(root_hash.to_string())).unwrap();
+        assert!(root_content.contains("tree\tda39a3ee5e6b4b0d3255bfef95601890afd80709\tempty\n"));
+    }
+
+    #[test]
+    fn add_blob_test() {
+        let (_dir, repo_root) = temp_repo();
+        touch(&repo_root, &["src/cli.rs"]);
+        let stored = Oid::of(b"stored on its own");
+        let mut tree = Tree::new_in(&repo_root).unwrap();
+        assert!(tree.add_path(Path::new("src/cli.rs")));
+        // doesn't exist in the working tree, and doesn't have to.
+        assert!(tree.add_blob(Path::new("src/patched.rs"), stored));
+        assert!(!tree.add_blob(Path::new(""), stored));
+        assert!(!tree.add_blob(Path::new("src/cli.rs/under_a_file"), stored));
+        let root_hash = tree.to_object_file().unwrap();
+
+        let dirs_path = repo_root.join(".gyat").join("dirs");
+        let root_content = fs::read_to_string(dirs_path.join(root_hash.to_string())).unwrap();
+        let src_hash = root_content.split('\t').nth(1).unwrap();
+        let src_content = fs::read_to_string(dirs_path.join(src_hash)).unwrap();
+        assert!(src_content.contains(&format!("blob\t{stored}\tpatched.rs\n")));
+        assert!(src_content.contains("\tcli.rs\n"));
     }
 }
@@ -1,12 +1,17 @@
 use std::{
-    collections::{HashMap, VecDeque},
-    fs::{self, File},
-    io::{BufRead, BufReader},
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    fs::File,
+    io::{BufRead, BufReader, Read},
     path::{Component, Path, PathBuf},
 };
 
 use crate::Result;
-use crate::{dirtree::Tree, hash};
+use crate::{
+    hash::{self, Oid},
+    objects,
+    repo::Repository,
+    root, utils,
+};
 
 /// No I/O normalization.
 ///
@@ -31,23 +36,45 @@ pub fn normalize(path: &Path) -> PathBuf {
 
 /// Traverses the given path.
 ///
+/// `.gyat` directories are skipped, along with everything inside them. Symlinks to directories
+/// are returned as they are, not followed, so a link pointing back up the tree can't loop forever.
+///
 /// # Parameters
 /// * `path`: the given path
 ///
 /// # Returns
 /// - A Vec of PathBufs
 pub fn traverse_path(path: &Path) -> Result<Vec<PathBuf>> {
-    let mut ret = Vec::new();
-    let mut pathbuf_queue: VecDeque<PathBuf> = VecDeque::new();
-    pathbuf_queue.push_back(path.to_path_buf());
-    // technically BFS, but this is a tree. So no need for a HashSet here.
-    // Another way of doing this is using recursion.
+    traverse_path_depth(path, None)
+}
 
-    while let Some(pathbuf) = pathbuf_queue.pop_front() {
-        if !pathbuf.is_dir() {
+/// Like `traverse_path`, but doesn't go deeper than `max_depth`.
+///
+/// # Parameters
+/// * `path`: the given path. It's at depth 0, whatever is directly inside it at depth 1, etc.
+/// * `max_depth`: the deepest entries to return, directories at that depth aren't read. None for
+///   no limit.
+///
+/// # Returns
+/// - A Vec of PathBufs
+pub fn traverse_path_depth(path: &Path, max_depth: Option<usize>) -> Result<Vec<PathBuf>> {
+    let mut ret = Vec::new();
+    let mut pathbuf_queue: VecDeque<(PathBuf, usize)> = VecDeque::new();
+    pathbuf_queue.push_back((path.to_path_buf(), 0));
+    // symlinks aren't followed, but `path` itself may be one, and bind mounts can still make a
+    // directory show up twice. So every directory read is remembered.
+    let mut visited = HashSet::new();
+
+    while let Some((pathbuf, depth)) = pathbuf_queue.pop_front() {
+        // only `path` itself gets followed if it's a symlink.
+        let is_link = depth > 0 && pathbuf.is_symlink();
+        if is_link || !pathbuf.is_dir() || max_depth.is_some_and(|max| depth >= max) {
             ret.push(pathbuf);
             continue;
         }
+        if !visited.insert(pathbuf.canonicalize().unwrap_or_else(|_| pathbuf.clone())) {
+            continue;
+        }
 
         // I think the only possible error here is "lack of permission"
         for p in pathbuf.read_dir()? {
@@ -56,7 +83,11 @@ pub fn traverse_path(path: &Path) -> Result<Vec<PathBuf>> {
                 Ok(p) => p,
                 Err(_) => continue,
             };
-            pathbuf_queue.push_back(p.path());
+            // a directory with `.gyat` in it is a repository root, and `.gyat` is never observed.
+            if p.file_name() == ".gyat" && p.file_type().is_ok_and(|t| t.is_dir()) {
+                continue;
+            }
+            pathbuf_queue.push_back((p.path(), depth + 1));
         }
         ret.push(pathbuf);
     }
@@ -69,19 +100,32 @@ pub fn get_files_and_dirs(path: &Path) -> Result<(Vec<PathBuf>, Vec<PathBuf>)> {
     Ok(traverse_path(path)?.into_iter().partition(|p| p.is_dir()))
 }
 
+/// Files and symlinks under `path`. Symlinks to directories aren't in there, `traverse_path`
+/// doesn't follow them and there's no file to hash, so they're skipped altogether.
 #[inline]
 pub fn get_files_and_syms(path: &Path) -> Result<Vec<PathBuf>> {
     Ok(traverse_path(path)?
         .into_iter()
-        .filter(|p| p.is_file() || p.is_symlink())
+        .filter(|p| p.is_file() || (p.is_symlink() && !p.is_dir()))
         .collect())
 }
 
+/// Directories under `path` (including `path` itself) that have nothing inside. A symlink to an
+/// empty directory isn't one.
+pub fn get_empty_dirs(path: &Path) -> Result<Vec<PathBuf>> {
+    Ok(get_dirs(path)?
+        .into_iter()
+        .filter(|p| p.read_dir().is_ok_and(|mut entries| entries.next().is_none()))
+        .collect())
+}
+
+/// Directories under `path`, including `path` itself. Like `traverse_path`, only `path` is
+/// followed if it's a symlink.
 #[inline]
 pub fn get_dirs(path: &Path) -> Result<Vec<PathBuf>> {
     Ok(traverse_path(path)?
         .into_iter()
-        .filter(|p| p.is_dir())
+        .filter(|p| p.is_dir() && (p == path || !p.is_symlink()))
         .collect())
 }
 
@@ -92,15 +136,18 @@ pub fn get_dirs(path: &Path) -> Result<Vec<PathBuf>> {
 /// * `path`:
 pub struct IndexEntry {
     pub perm: u8,
-    pub hash: [u8; 20],
+    pub hash: Oid,
     pub path: PathBuf,
     pub change: ChangeType,
 }
 
-/// Reads the (new-format) index file.
+/// Reads the index file, in either format:
+/// - The new format: `perm\thash\tpath\tchange`.
+/// - The old format: `perm\thash\tpath`. It predates change types, so every entry is read as
+///   `ChangeType::New`.
 ///
-/// * `index_file`:
-pub fn read_index(index_file: &mut File) -> Result<Vec<IndexEntry>> {
+/// * `index_file`: usually `.gyat/index`, but anything readable works.
+pub fn read_index(index_file: &mut impl Read) -> Result<Vec<IndexEntry>> {
     let mut files = Vec::new();
     let mut reader = BufReader::new(index_file);
     let mut buf = String::new();
@@ -108,15 +155,21 @@ pub fn read_index(index_file: &mut File) -> Result<Vec<IndexEntry>> {
         buf.clear();
         reader.read_line(&mut buf)? > 0
     } {
-        let parts: Vec<_> = buf.trim().split('\t').collect();
-        let perm = parts[0].parse::<u8>().unwrap();
-        let hash = hash::from_string(parts[1])?;
+        // only the line ending, paths can start or end with spaces.
+        let line = buf.strip_suffix('\n').unwrap_or(&buf);
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        let parts: Vec<_> = line.split('\t').collect();
+        if parts.len() != 3 && parts.len() != 4 {
+            return Err(format!("Invalid index line {}", line).into());
+        }
+        let perm = parts[0]
+            .parse::<u8>()
+            .map_err(|_| format!("Invalid index line {}", line))?;
+        let hash = parts[1].parse()?;
         let path = PathBuf::from(parts[2]);
-        let change = match parts[3] {
-            "New" => ChangeType::New,
-            "Mod" => ChangeType::Mod,
-            "Del" => ChangeType::Del,
-            _ => return Err(format!("Invalid change {}", parts[3]).into()),
+        let change = match parts.get(3) {
+            None => ChangeType::New,
+            Some(token) => ChangeType::from_token(token)?,
         };
 
         files.push(IndexEntry {
@@ -130,70 +183,337 @@ pub fn read_index(index_file: &mut File) -> Result<Vec<IndexEntry>> {
     Ok(files)
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum ChangeType {
     New,
     Mod,
     Del,
 }
 
-pub fn see_changes(
-    observed_files: Vec<(u8, String, PathBuf)>,
-    blob_map: &mut HashMap<PathBuf, String>,
-    dirtree: &mut Tree,
-) -> Result<Vec<(ChangeType, PathBuf)>> {
-    let mut changes = Vec::new();
-
-    for (_, idx_hash, path) in observed_files {
-        match blob_map.remove(&path) {
-            Some(blob_hash) => {
-                if blob_hash == idx_hash {
-                    //Unchanged
-                    continue;
-                } else {
-                    //Modified
-                    dirtree.add_path(&path);
-                    changes.push((ChangeType::Mod, path));
-                }
-            }
-            None => {
-                //New
-                dirtree.add_path(&path);
-                changes.push((ChangeType::New, path));
-            }
+/// Reserved for renames, once they're detected. Nothing writes it yet.
+const RENAME_TOKEN: &str = "Ren";
+
+impl ChangeType {
+    /// How the change is written in the index and in a commit's list of changes. These are part
+    /// of the on-disk format, so they must never change, even if the variants are renamed.
+    pub fn as_token(&self) -> &'static str {
+        match self {
+            ChangeType::New => "New",
+            ChangeType::Mod => "Mod",
+            ChangeType::Del => "Del",
         }
     }
 
-    for (deleted_path, _) in blob_map.drain() {
-        //Deleted
-        changes.push((ChangeType::Del, deleted_path));
+    /// The opposite of `as_token`.
+    ///
+    /// # Return values
+    /// - Err if `token` isn't one, or is reserved for a change this version doesn't know yet.
+    ///
+    /// * `token`: the last field of an index line, or the first of a commit's change line.
+    pub fn from_token(token: &str) -> Result<Self> {
+        match token {
+            "New" => Ok(ChangeType::New),
+            "Mod" => Ok(ChangeType::Mod),
+            "Del" => Ok(ChangeType::Del),
+            RENAME_TOKEN => Err("Renames in the index are not supported yet".into()),
+            _ => Err(format!("Invalid change {}", token).into()),
+        }
     }
+}
 
-    Ok(changes)
+/// Compares what's observed against what was there before, eg, the blobs of HEAD.
+/// Unchanged paths are not reported, and nothing is written or added anywhere.
+///
+/// # Return value
+/// The changes, sorted by path. Paths in `previous` but not in `observed` are deleted.
+///
+/// * `observed`: see `observed_contents`.
+/// * `previous`: path to blob hash, see `objects::get_blobs_from_root`.
+pub fn diff_observed(
+    observed: &[ObservedContent],
+    previous: &BTreeMap<PathBuf, Oid>,
+) -> Vec<(ChangeType, PathBuf)> {
+    // `objects::diff_blobs` does exactly that, on the observed paths and hashes.
+    let current: BTreeMap<PathBuf, Oid> = observed
+        .iter()
+        .map(|oc| (oc.path.clone(), oc.hash))
+        .collect();
+    objects::diff_blobs(previous, &current)
+        .into_iter()
+        .map(|(change, path, ..)| (change, path))
+        .collect()
 }
 
 /// [Nam Vu] I modified this method so that it can get any root tree hash from a specified commit, and if None is given it will just return the lastest commit
-pub fn get_root_tree_hash(gyat_path: &Path, commit_hash: Option<&String>) -> Result<Option<String>> {
+///
+/// The commit itself is read with `objects::read_commit_content`, so there's only one commit
+/// parser.
+///
+/// # Return values
+/// - Err if the commit cannot be read, or has no `Tree:` line.
+/// - Ok(None) if no commit is given and there's no commit yet.
+/// - Ok(Some(Oid)) with the root tree hash otherwise.
+///
+/// * `gyat_path`: the repository's `.gyat`, `HEAD` and the commit are read from it.
+/// * `commit_hash`:
+pub fn get_root_tree_hash(gyat_path: &Path, commit_hash: Option<&String>) -> Result<Option<Oid>> {
     // If no commit hash is provided, default to HEAD
     let commit_hash = match commit_hash {
         Some(hash) => hash.to_string(),
-        None => fs::read_to_string(gyat_path.join("HEAD"))?.trim().to_string(),
+        None => match utils::read_head(gyat_path)? {
+            Some(head) => head,
+            None => return Ok(None),
+        },
+    };
+
+    let commit = objects::read_commit_at(&gyat_path.join("commits"), &commit_hash.parse()?)?;
+    Ok(Some(commit.root))
+}
+
+/// What `observe` would stage for `paths`: every file or empty directory under them that differs
+/// from HEAD. Nothing is written, staging the result is up to the caller.
+///
+/// # Return values
+/// - Err if a path doesn't exist or is outside the repository, or for any I/O error.
+/// - Ok(Vec<IndexEntry>) otherwise. The hash of a deleted path is its hash in HEAD.
+///
+/// * `repo`:
+/// * `paths`: relative to the current directory, or absolute.
+pub fn compute_changes(repo: &Repository, paths: &[PathBuf]) -> Result<Vec<IndexEntry>> {
+    let repo_root = &repo.paths().repo_root;
+    // every path is made absolute up front, so nothing below cares where `observe` was run from.
+    let cwd = std::env::current_dir()?;
+    let mut observed_paths = Vec::with_capacity(paths.len());
+    for path in paths {
+        observed_paths.push(repo_root.join(root::repo_relative(path, &cwd, repo_root)?));
+    }
+
+    // read before hashing anything, a broken HEAD should fail fast.
+    let head_blobs = match repo.head_commit()? {
+        Some(head) => Some(commit_blobs(repo, &head)?),
+        None => None,
+    };
+    let observe_list = observed_contents(&observed_paths, repo_root)?;
+
+    // check modification status.
+    // We only care about files that are changed.
+    let prev_comp: BTreeMap<PathBuf, Oid> = match head_blobs {
+        // these blobs were in both the last commit tree and the staged tree.
+        Some(head_blobs) => head_blobs
+            .into_iter()
+            .filter(|pair| {
+                observed_paths
+                    .iter()
+                    .any(|p| pair.0.starts_with(p.strip_prefix(repo_root).unwrap()))
+            })
+            .collect(),
+        // there's no previous commit yet, so everything is new.
+        None => BTreeMap::new(),
+    };
+
+    Ok(staged_changes(&observe_list, &prev_comp))
+}
+
+/// Every blob of a commit, for comparing the working tree against it.
+///
+/// # Return values
+/// - Err saying the commit is corrupt if it, or any tree in it, is missing or can't be read. This
+///   is what happens after objects were lost, and a plain "Tree hash ... doesn't exist" doesn't
+///   say what to do about it.
+/// - Ok(BTreeMap) otherwise, see `Repository::get_blobs_from_root`.
+///
+/// * `repo`:
+/// * `commit_hash`: usually HEAD.
+fn commit_blobs(repo: &Repository, commit_hash: &Oid) -> Result<BTreeMap<PathBuf, Oid>> {
+    repo.read_commit(commit_hash)
+        .and_then(|commit| repo.get_blobs_from_root(&commit.root))
+        .map_err(|e| {
+            format!(
+                "HEAD commit {commit_hash} is corrupt or its tree is missing ({e}), run `gyat wood \
+                 --verify` to see what's broken"
+            )
+            .into()
+        })
+}
+
+/// Whether a path is the `.gyat` directory or anything inside it, which never gets observed.
+///
+/// This compares whole components, so `.gyatignore` or `.gyat-notes/` are still fair game.
+///
+/// * `root_relative`: relative to the repository root.
+fn is_gyat_dir(root_relative: &Path) -> bool {
+    root_relative.starts_with(".gyat")
+}
+
+/// Whether a path is ignored: the `.gyat` directory, or whatever `.gyatignore` or
+/// `.gyat/info/exclude` matches. The latter is for ignores that aren't shared, since it's never
+/// tracked.
+///
+/// # Return values
+/// - Err if an ignore file cannot be read or the patterns aren't valid. A missing ignore file just
+///   has no patterns.
+/// - Ok(matcher) otherwise, which takes paths relative to the repository root.
+///
+/// * `repo_root`:
+pub fn ignore_matcher(repo_root: &Path) -> Result<impl Fn(&Path) -> bool> {
+    // build the regex, from the non-empty lines of both ignore files.
+    let mut patterns = Vec::new();
+    for ignore_file in [
+        repo_root.join(".gyatignore"),
+        repo_root.join(".gyat").join("info").join("exclude"),
+    ] {
+        let Ok(f) = File::open(ignore_file) else {
+            continue;
+        };
+        for line in BufReader::new(f).lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                patterns.push(line.trim().to_string());
+            }
+        }
+    }
+    let matcher = if patterns.is_empty() {
+        None
+    } else {
+        Some(rare::RARE::new(&patterns.join("|"))?)
     };
+    Ok(move |root_relative: &Path| {
+        is_gyat_dir(root_relative)
+            || matcher
+                .as_ref()
+                .is_some_and(|m| m.is_match(&root_relative.to_string_lossy()))
+    })
+}
 
-    if commit_hash.is_empty() {
-        return Ok(None);
+/// Hashes every file and finds every empty directory under `observed_paths`, skipping whatever
+/// `ignore_matcher` ignores.
+///
+/// # Return values
+/// - Err if there's I/O error or the patterns aren't valid.
+///
+/// * `observed_paths`: absolute paths inside `repo_root`.
+/// * `repo_root`: the repository root. Paths in the result are relative to it.
+pub fn observed_contents(
+    observed_paths: &[PathBuf],
+    repo_root: &Path,
+) -> Result<Vec<ObservedContent>> {
+    let is_ignored = ignore_matcher(repo_root)?;
+
+    // listed up front, so the progress knows the total.
+    let mut files = Vec::new();
+    for path in observed_paths.iter() {
+        // this guarantees that for this dirtree, any leaf inside the tree is a file.
+        for subdir in get_files_and_syms(path)? {
+            let root_relative = normalize(&subdir);
+            if !is_ignored(root_relative.strip_prefix(repo_root)?) {
+                files.push(root_relative);
+            }
+        }
+    }
+    let mut observe_list: Vec<ObservedContent> = Vec::with_capacity(files.len());
+    let mut progress = utils::Progress::new("hashing", files.len());
+    for file in &files {
+        observe_list.push(observe_single_path(file, repo_root)?);
+        progress.tick();
     }
+    progress.finish();
+
+    for path in observed_paths.iter() {
+        // empty directories are tracked too, see `objects::EMPTY_DIR`.
+        for empty_dir in get_empty_dirs(path)? {
+            let root_relative = empty_dir.strip_prefix(repo_root)?;
+            if root_relative.as_os_str().is_empty() || is_ignored(root_relative) {
+                continue;
+            }
+            observe_list.push(ObservedContent {
+                perm: b'1',
+                hash: objects::EMPTY_DIR,
+                path: root_relative.to_owned(),
+            });
+        }
+    }
+
+    Ok(observe_list)
+}
+
+/// Figures out the changes with ChangeType::New, ChangeType::Mod or ChangeType::Del to stage.
+/// Just a helper function for `compute_changes`.
+/// Without a previous commit, `prev_comp` is empty and everything is new.
+///
+/// * `observe_list`:
+/// * `prev_comp`:
+fn staged_changes(
+    observe_list: &[ObservedContent],
+    prev_comp: &BTreeMap<PathBuf, Oid>,
+) -> Vec<IndexEntry> {
+    // the logic: for each file:
+    // - if it doesn't exist in the last commit tree, it is a new file.
+    // - if its SHA1 does change, it is modified.
+    // - if its SHA1 doesn't change, it is unchanged and we don't need to track it.
+    //
+    // finally, anything that is in the last commit tree but not in the current commit tree in
+    // `prev_comp` was deleted.
+    // `diff_observed` does exactly that, we just need the permissions and hashes back.
+    let observed: HashMap<&Path, &ObservedContent> = observe_list
+        .iter()
+        .map(|oc| (oc.path.as_path(), oc))
+        .collect();
+
+    diff_observed(observe_list, prev_comp)
+        .into_iter()
+        .map(|(change, path)| {
+            let (perm, hash) = match observed.get(path.as_path()) {
+                Some(oc) => (oc.perm, oc.hash),
+                // lazy ass me. Deleted files get 1, and keep their hash in HEAD.
+                None => (b'1', prev_comp[&path]),
+            };
+            IndexEntry {
+                perm,
+                hash,
+                path,
+                change,
+            }
+        })
+        .collect()
+}
 
-    let commit_path = gyat_path.join("commits").join(commit_hash);
-    let commit_content = fs::read_to_string(commit_path)?;
+/// A file (or empty directory) as it currently is in the working tree.
+pub struct ObservedContent {
+    pub perm: u8,
+    pub hash: Oid,
+    /// Relative to the repository root.
+    pub path: PathBuf,
+}
 
-    let root_tree_hash = commit_content
-        .lines()
-        .find(|line| line.starts_with("Tree: "))
-        .map(|hash| hash[6..].to_string())
-        .ok_or("Missing tree")?;
+/// Hashes a single file, for `observed_contents`.
+///
+/// # Return values
+/// - Err if there's I/O error.
+///
+/// * `path`: the path. Make sure the path is a file.
+/// * `repo_root`: `path` must be in `repo_root`.
+/// * `index_file`: the ".gyat/index" file.
+fn observe_single_path(path: &Path, repo_root: &Path) -> Result<ObservedContent> {
+    if !path.exists() {
+        return Err(format!("{} doesn't exist", path.display()).into());
+    }
+    if !path.starts_with(repo_root) {
+        return Err(format!(
+            "Path {} is not in repository root {}",
+            path.display(),
+            repo_root.display()
+        )
+        .into());
+    }
 
-    Ok(Some(root_tree_hash))
+    let mut blob_source = File::open(path)?;
+    let perm = path.metadata()?.permissions();
+    let hash = hash::digest_file(&mut blob_source)?;
+    Ok(ObservedContent {
+        perm: if perm.readonly() { b'0' } else { b'1' },
+        hash,
+        path: path.strip_prefix(repo_root)?.to_owned(),
+    })
 }
 
 #[cfg(test)]
@@ -218,6 +538,159 @@ mod test {
         }
     }
 
+    #[test]
+    fn read_index_test() {
+        let (a, b) = (
+            "50e1925c5061668ee13535a47e49915d89324a7a",
+            "4786eb5f727770a4ee3eae98542110c624bf4a1f",
+        );
+        let new_format = format!("1\t{a}\ta.txt\tMod\n0\t{b}\tdir/b.txt\tDel\n");
+        let entries = read_index(&mut new_format.as_bytes()).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].perm, 1);
+        assert_eq!(entries[0].path, Path::new("a.txt"));
+        assert_eq!(entries[0].change, ChangeType::Mod);
+        assert_eq!(entries[1].perm, 0);
+        assert_eq!(entries[1].hash, b.parse().unwrap());
+        assert_eq!(entries[1].change, ChangeType::Del);
+
+        let old_format = format!("1\t{a}\ta.txt\n1\t{b}\tdir/b.txt\n");
+        let entries = read_index(&mut old_format.as_bytes()).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].path, Path::new("dir/b.txt"));
+        assert!(entries.iter().all(|e| e.change == ChangeType::New));
+
+        let spaces = format!("1\t{a}\t lead and trail \tMod\r\n1\t{b}\ttrail \n");
+        let entries = read_index(&mut spaces.as_bytes()).unwrap();
+        assert_eq!(entries[0].path, Path::new(" lead and trail "));
+        assert_eq!(entries[0].change, ChangeType::Mod);
+        assert_eq!(entries[1].path, Path::new("trail "));
+
+        assert!(read_index(&mut format!("1\t{a}\n").as_bytes()).is_err());
+        // a hand-edited perm used to panic.
+        assert!(read_index(&mut format!("rw\t{a}\ta.txt\tNew\n").as_bytes()).is_err());
+        assert!(read_index(&mut format!("\t{a}\ta.txt\n").as_bytes()).is_err());
+        assert!(read_index(&mut format!("1\t{a}\ta.txt\tBad\n").as_bytes()).is_err());
+        assert!(read_index(&mut format!("1\t{a}\ta.txt\tRen\n").as_bytes()).is_err());
+    }
+
+    #[test]
+    fn change_token_test() {
+        for change in [ChangeType::New, ChangeType::Mod, ChangeType::Del] {
+            assert_eq!(ChangeType::from_token(change.as_token()).unwrap(), change);
+        }
+        // what indexes written so far hold.
+        assert_eq!(ChangeType::Del.as_token(), "Del");
+        assert!(ChangeType::from_token(RENAME_TOKEN).is_err());
+        assert!(ChangeType::from_token("new").is_err());
+    }
+
+    #[test]
+    fn diff_observed_test() {
+        let (a, b, c) = (Oid::of(b"a"), Oid::of(b"b"), Oid::of(b"c"));
+        let previous = BTreeMap::from([
+            (PathBuf::from("same.txt"), a),
+            (PathBuf::from("mod.txt"), b),
+            (PathBuf::from("gone.txt"), c),
+        ]);
+        let observed = |hash, path: &str| ObservedContent {
+            perm: b'1',
+            hash,
+            path: PathBuf::from(path),
+        };
+        let observed = [
+            observed(c, "new.txt"),
+            observed(a, "same.txt"),
+            observed(a, "mod.txt"),
+        ];
+        assert_eq!(
+            diff_observed(&observed, &previous),
+            [
+                (ChangeType::Del, PathBuf::from("gone.txt")),
+                (ChangeType::Mod, PathBuf::from("mod.txt")),
+                (ChangeType::New, PathBuf::from("new.txt")),
+            ]
+        );
+        assert!(diff_observed(&[], &BTreeMap::new()).is_empty());
+    }
+
+    #[test]
+    fn get_root_tree_hash_test() {
+        let (_dir, repo_root) = utils::temp_repo();
+        let repo = Repository::open_in(&repo_root).unwrap();
+        let gyat_path = &repo.paths().gyat_path;
+        let tree = Oid::of(b"get_root_tree_hash tree");
+        let commit = repo
+            .write_commit(&format!(
+                "Parent: 0\nTree: {tree}\nMessage: root\nChanges:\n"
+            ))
+            .unwrap();
+        assert_eq!(
+            get_root_tree_hash(gyat_path, Some(&commit.to_string())).unwrap(),
+            Some(tree)
+        );
+        // no tree line is an error, not some made up hash.
+        let broken = repo.write_commit("Parent: 0\nMessage: no tree\n").unwrap();
+        assert!(get_root_tree_hash(gyat_path, Some(&broken.to_string())).is_err());
+
+        // nothing committed yet.
+        assert_eq!(get_root_tree_hash(gyat_path, None).unwrap(), None);
+        utils::update_head(gyat_path, &commit.to_string(), "test").unwrap();
+        assert_eq!(get_root_tree_hash(gyat_path, None).unwrap(), Some(tree));
+    }
+
+    #[test]
+    fn traverse_depth_test() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        fs::create_dir_all(root.join("a/b/c")).unwrap();
+        fs::write(root.join("top.txt"), "").unwrap();
+        fs::write(root.join("a/b/deep.txt"), "").unwrap();
+
+        let relative = |max_depth| -> HashSet<PathBuf> {
+            traverse_path_depth(root, max_depth)
+                .unwrap()
+                .into_iter()
+                .map(|p| p.strip_prefix(root).unwrap().to_path_buf())
+                .collect()
+        };
+        let set = |paths: &[&str]| paths.iter().map(PathBuf::from).collect::<HashSet<_>>();
+        assert_eq!(relative(Some(0)), set(&[""]));
+        assert_eq!(relative(Some(1)), set(&["", "a", "top.txt"]));
+        assert_eq!(relative(Some(2)), set(&["", "a", "top.txt", "a/b"]));
+        assert_eq!(
+            relative(None),
+            set(&["", "a", "top.txt", "a/b", "a/b/c", "a/b/deep.txt"])
+        );
+        assert_eq!(relative(None), relative(Some(100)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn traverse_skip_test() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        fs::create_dir_all(root.join(".gyat/files")).unwrap();
+        fs::create_dir_all(root.join("a")).unwrap();
+        fs::write(root.join("a/file.txt"), "").unwrap();
+        fs::write(root.join(".gyatignore"), "").unwrap();
+        // would loop forever if it were followed.
+        std::os::unix::fs::symlink(root, root.join("a/loop")).unwrap();
+
+        let relative: HashSet<PathBuf> = traverse_path(root)
+            .unwrap()
+            .into_iter()
+            .map(|p| p.strip_prefix(root).unwrap().to_path_buf())
+            .collect();
+        let expected = ["", "a", "a/file.txt", "a/loop", ".gyatignore"];
+        assert_eq!(relative, expected.iter().map(PathBuf::from).collect());
+        // a symlink to a directory isn't a file, nor a directory to look into.
+        assert!(!get_files_and_syms(root)
+            .unwrap()
+            .contains(&root.join("a/loop")));
+        assert!(!get_dirs(root).unwrap().contains(&root.join("a/loop")));
+    }
+
     #[test]
     /// Checks the traversal function.
     fn test_traversal() {
@@ -226,6 +699,7 @@ mod test {
             Path::new("test-data").into(),
             Path::new("test-data/another-test-dir").into(),
             Path::new("test-data/cargo-mimic.txt").into(),
+            Path::new("test-data/example.png").into(),
         ]
         .into_iter()
         .collect();
@@ -253,9 +727,12 @@ mod test {
         }
 
         let ret_pathbufs = get_files_and_syms(Path::new("test-data")).unwrap();
-        let path_hash: HashSet<PathBuf> = vec![Path::new("test-data/cargo-mimic.txt").into()]
-            .into_iter()
-            .collect();
+        let path_hash: HashSet<PathBuf> = vec![
+            Path::new("test-data/cargo-mimic.txt").into(),
+            Path::new("test-data/example.png").into(),
+        ]
+        .into_iter()
+        .collect();
 
         for pb in &ret_pathbufs {
             assert!(
@@ -264,4 +741,275 @@ mod test {
             );
         }
     }
+
+    #[test]
+    fn exclude_test() {
+        let tmp = tempfile::tempdir().unwrap();
+        let repo_root = root::init_repo(&tmp.path().join("repo")).unwrap();
+        for name in ["shared.log", "mine.tmp", "kept.txt"] {
+            std::fs::write(repo_root.join(name), name).unwrap();
+        }
+        let observed = || -> Vec<PathBuf> {
+            let mut paths: Vec<PathBuf> =
+                observed_contents(std::slice::from_ref(&repo_root), &repo_root)
+                    .unwrap()
+                    .into_iter()
+                    .map(|oc| oc.path)
+                    .collect();
+            paths.sort();
+            paths
+        };
+        let exclude = repo_root.join(".gyat/info/exclude");
+        assert_eq!(std::fs::read_to_string(&exclude).unwrap(), "");
+
+        std::fs::write(repo_root.join(".gyatignore"), "\\.log$\n").unwrap();
+        std::fs::write(&exclude, "\\.tmp$\n\n").unwrap();
+        assert_eq!(
+            observed(),
+            [Path::new(".gyatignore"), Path::new("kept.txt")]
+        );
+
+        // repositories made before `info/exclude` existed don't have one.
+        std::fs::remove_dir_all(repo_root.join(".gyat/info")).unwrap();
+        assert_eq!(
+            observed(),
+            [
+                Path::new(".gyatignore"),
+                Path::new("kept.txt"),
+                Path::new("mine.tmp")
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn observe_dir_symlink_test() {
+        let (_dir, repo_root) = utils::temp_repo();
+        std::fs::create_dir_all(repo_root.join("src")).unwrap();
+        std::fs::write(repo_root.join("src/lib.rs"), "lib").unwrap();
+        std::fs::create_dir(repo_root.join("empty")).unwrap();
+        std::os::unix::fs::symlink("src", repo_root.join("linkdir")).unwrap();
+        std::os::unix::fs::symlink("empty", repo_root.join("emptylink")).unwrap();
+
+        let mut observed: Vec<_> = observed_contents(std::slice::from_ref(&repo_root), &repo_root)
+            .unwrap()
+            .into_iter()
+            .map(|oc| (oc.path, oc.hash))
+            .collect();
+        observed.sort();
+        // neither link is observed, and `empty` only once.
+        assert_eq!(
+            observed,
+            [
+                (PathBuf::from("empty"), objects::EMPTY_DIR),
+                (PathBuf::from("src/lib.rs"), hash::digest_bytes(b"lib")),
+            ]
+        );
+    }
+
+    #[test]
+    fn commit_blobs_test() {
+        let (_dir, repo_root) = utils::temp_repo();
+        let repo = Repository::open_in(&repo_root).unwrap();
+        // a commit whose tree was lost.
+        let tree = Oid::of(b"commit_blobs_test missing tree");
+        let commit_hash = repo
+            .write_commit(&format!(
+                "Parent: 0\nTree: {tree}\nAuthor: me <>\nMessage: lost\nDate: now\nChanges:\n"
+            ))
+            .unwrap();
+        let err = commit_blobs(&repo, &commit_hash).unwrap_err().to_string();
+        assert!(err.starts_with(&format!(
+            "HEAD commit {commit_hash} is corrupt or its tree is missing"
+        )));
+        assert!(err.contains(&tree.to_string()));
+        let missing = Oid::of(b"commit_blobs_test missing commit");
+        assert!(commit_blobs(&repo, &missing)
+            .unwrap_err()
+            .to_string()
+            .contains("is corrupt"));
+
+        let empty = repo
+            .write_commit(&format!(
+                "Parent: 0\nTree: {}\nAuthor: me <>\nMessage: empty\nDate: now\nChanges:\n",
+                objects::EMPTY_TREE
+            ))
+            .unwrap();
+        std::fs::write(
+            repo.paths().dirs_path.join(objects::EMPTY_TREE.to_string()),
+            "",
+        )
+        .unwrap();
+        assert!(commit_blobs(&repo, &empty).unwrap().is_empty());
+    }
+
+    #[test]
+    fn staged_changes_test() {
+        let (a, b, c) = (Oid::of(b"a"), Oid::of(b"b"), Oid::of(b"c"));
+        let observed = |perm, hash, path: &str| ObservedContent {
+            perm,
+            hash,
+            path: PathBuf::from(path),
+        };
+        let observe_list = [
+            observed(b'0', a, "same.txt"),
+            observed(b'0', c, "changed.txt"),
+            observed(b'1', b, "added.txt"),
+        ];
+        let previous: BTreeMap<PathBuf, Oid> =
+            [("same.txt", a), ("changed.txt", b), ("gone.txt", c)]
+                .into_iter()
+                .map(|(path, hash)| (PathBuf::from(path), hash))
+                .collect();
+
+        let summary = |entries: Vec<IndexEntry>| -> Vec<(ChangeType, String, Oid, u8)> {
+            entries
+                .into_iter()
+                .map(|e| (e.change, e.path.display().to_string(), e.hash, e.perm))
+                .collect()
+        };
+        // a deleted path keeps its old hash.
+        assert_eq!(
+            summary(staged_changes(&observe_list, &previous)),
+            [
+                (ChangeType::New, "added.txt".to_string(), b, b'1'),
+                (ChangeType::Mod, "changed.txt".to_string(), c, b'0'),
+                (ChangeType::Del, "gone.txt".to_string(), c, b'1'),
+            ]
+        );
+        // without a previous commit everything is new.
+        assert!(staged_changes(&observe_list, &BTreeMap::new())
+            .iter()
+            .all(|e| e.change == ChangeType::New));
+    }
+
+    #[test]
+    fn compute_changes_test() {
+        let (_dir, repo_root) = utils::temp_repo();
+        let repo = Repository::open_in(&repo_root).unwrap();
+        for (path, content) in [
+            ("same.txt", "same"),
+            ("changed.txt", "before"),
+            ("gone.txt", "gone"),
+            ("src/lib.rs", "lib"),
+        ] {
+            let path = repo_root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        let mut tree = crate::dirtree::Tree::new_in(&repo_root).unwrap();
+        tree.add_path(&repo_root);
+        let root_hash = tree.to_object_file().unwrap();
+        let head = repo
+            .write_commit(&format!(
+                "Parent: 0\nTree: {root_hash}\nMessage: head\nChanges:\n"
+            ))
+            .unwrap();
+        utils::update_head(&repo.paths().gyat_path, &head.to_string(), "test").unwrap();
+
+        fs::write(repo_root.join("changed.txt"), "after").unwrap();
+        fs::remove_file(repo_root.join("gone.txt")).unwrap();
+        fs::write(repo_root.join("added.txt"), "added").unwrap();
+        fs::write(repo_root.join("src/lib.rs"), "lib, changed").unwrap();
+
+        let changes = |paths: &[&str]| -> Vec<(ChangeType, PathBuf)> {
+            let paths: Vec<PathBuf> = paths.iter().map(|p| repo_root.join(p)).collect();
+            compute_changes(&repo, &paths)
+                .unwrap()
+                .into_iter()
+                .map(|e| (e.change, e.path))
+                .collect()
+        };
+        assert_eq!(
+            changes(&["."]),
+            [
+                (ChangeType::New, PathBuf::from("added.txt")),
+                (ChangeType::Mod, PathBuf::from("changed.txt")),
+                (ChangeType::Del, PathBuf::from("gone.txt")),
+                (ChangeType::Mod, PathBuf::from("src/lib.rs")),
+            ]
+        );
+        // only what's under the observed paths, HEAD's other files aren't deleted.
+        assert_eq!(
+            changes(&["src"]),
+            [(ChangeType::Mod, PathBuf::from("src/lib.rs"))]
+        );
+        assert!(changes(&["same.txt"]).is_empty());
+
+        // a deleted file is staged with its hash in HEAD.
+        let gone = compute_changes(&repo, std::slice::from_ref(&repo_root))
+            .unwrap()
+            .into_iter()
+            .find(|e| e.change == ChangeType::Del)
+            .unwrap();
+        assert_eq!(
+            Some(gone.hash),
+            repo.find_blob(&root_hash, Path::new("gone.txt")).unwrap()
+        );
+    }
+
+    #[test]
+    fn observe_from_subdirectory_test() {
+        let tmp = tempfile::tempdir().unwrap();
+        let repo_root = root::init_repo(&tmp.path().join("repo")).unwrap();
+        let src = repo_root.join("src");
+        std::fs::create_dir_all(src.join("cli")).unwrap();
+        std::fs::write(src.join("foo.txt"), "foo").unwrap();
+        std::fs::write(src.join("cli").join("bar.txt"), "bar").unwrap();
+        std::fs::write(repo_root.join("outside.txt"), "outside").unwrap();
+
+        // what `observe` does with its arguments when run from `repo/src`.
+        let observed_paths_from_src = |paths: &[&str]| -> Vec<PathBuf> {
+            let observed: Vec<PathBuf> = paths
+                .iter()
+                .map(|p| {
+                    repo_root.join(root::repo_relative(Path::new(p), &src, &repo_root).unwrap())
+                })
+                .collect();
+            let mut paths: Vec<PathBuf> = observed_contents(&observed, &repo_root)
+                .unwrap()
+                .into_iter()
+                .map(|oc| oc.path)
+                .collect();
+            paths.sort();
+            paths
+        };
+        assert_eq!(
+            observed_paths_from_src(&["foo.txt"]),
+            [Path::new("src/foo.txt")]
+        );
+        assert_eq!(
+            observed_paths_from_src(&["."]),
+            [Path::new("src/cli/bar.txt"), Path::new("src/foo.txt")]
+        );
+        assert_eq!(
+            observed_paths_from_src(&["cli/../../outside.txt"]),
+            [Path::new("outside.txt")]
+        );
+    }
+
+    #[test]
+    fn gyat_dir_excluded_test() {
+        let tmp = tempfile::tempdir().unwrap();
+        let repo_root = root::init_repo(&tmp.path().join("repo")).unwrap();
+        // only `.gyat` itself is special, not everything that starts like it.
+        std::fs::write(repo_root.join(".gyatignore-sample"), "sample").unwrap();
+        std::fs::create_dir(repo_root.join(".gyat-notes")).unwrap();
+        std::fs::write(repo_root.join(".gyat-notes").join("todo.txt"), "todo").unwrap();
+
+        let mut paths: Vec<PathBuf> =
+            observed_contents(std::slice::from_ref(&repo_root), &repo_root)
+                .unwrap()
+                .into_iter()
+                .map(|oc| oc.path)
+                .collect();
+        paths.sort();
+        assert_eq!(
+            paths,
+            [
+                Path::new(".gyat-notes/todo.txt"),
+                Path::new(".gyatignore-sample")
+            ]
+        );
+    }
 }
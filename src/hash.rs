@@ -3,30 +3,122 @@
 use crate::Result;
 use hex;
 use sha1::{Digest, Sha1};
-use std::{ffi::OsStr, fs, io::Read};
+use std::{ffi::OsStr, fmt, fs, str::FromStr};
 
-/// Digests the contents of a file into an SHA1 array.
+/// A SHA1, ie, the name of an object in `.gyat`.
+///
+/// Prints as the usual 40 hex digits, and parses back from them. Use this instead of passing
+/// `[u8; 20]` and hex strings around.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct Oid([u8; 20]);
+
+impl Oid {
+    pub const fn new(bytes: [u8; 20]) -> Self {
+        Self(bytes)
+    }
+
+    /// The SHA1 of `contents`.
+    ///
+    /// * `contents`:
+    pub fn of(contents: &[u8]) -> Self {
+        Self(get_sha1_bytes(contents))
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 20] {
+        &self.0
+    }
+}
+
+impl From<[u8; 20]> for Oid {
+    fn from(bytes: [u8; 20]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl fmt::Display for Oid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&hex::encode(self.0))
+    }
+}
+
+impl fmt::Debug for Oid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Oid({self})")
+    }
+}
+
+impl FromStr for Oid {
+    type Err = Box<dyn std::error::Error>;
+
+    /// # Return value
+    /// - Err if `s` isn't hex, or isn't exactly 20 bytes (40 digits) of it. Hashes come from object
+    ///   files too, so a corrupt one must not panic.
+    fn from_str(s: &str) -> Result<Self> {
+        let decoded = hex::decode(s)?;
+        if decoded.len() != 20 {
+            return Err(format!("Cannot convert {} into SHA1 bytes", s).into());
+        }
+        let mut bytes = [0; 20];
+        bytes.copy_from_slice(&decoded);
+        Ok(Self(bytes))
+    }
+}
+
+impl TryFrom<&str> for Oid {
+    type Error = Box<dyn std::error::Error>;
+
+    fn try_from(s: &str) -> Result<Self> {
+        s.parse()
+    }
+}
+
+/// The header every blob starts with, `blob <len>\0`. It's only stored in front of the compressed
+/// content, blob names don't include it, see `digest_file`.
+///
+/// * `len`: the size of the content in bytes, before compression.
+pub fn blob_header(len: u64) -> Vec<u8> {
+    format!("blob {len}\0").into_bytes()
+}
+
+/// Digests the contents of a file into its blob hash, the SHA1 of the contents zero-padded to a
+/// multiple of 1024 bytes.
+///
+/// That's how blobs have always been named, so it must not change, or every tracked file of every
+/// existing repository would look modified. The padding does mean that contents only differing in
+/// trailing zeros share a name. The header in the stored blob keeps the real length, so whichever
+/// of them is stored reads back whole.
 ///
 /// # Parameters
-/// * `file`: the file to digest.
+/// * `file`: the file to digest, from the start.
 /// # Returns
 /// - `Ok` with the hashed array.
 /// - `Err` if file reading fails.
-pub fn digest_file(file: &mut fs::File) -> Result<[u8; 20]> {
-    let mut buf: [u8; 1024] = [0; 1024];
-    let mut len = file.read(&mut buf[..])?;
+pub fn digest_file(file: &mut fs::File) -> Result<Oid> {
     let mut hasher = Sha1::new();
-    while len > 0 {
-        // if I don't qualify like this, there will be a conflict.
-        hasher = sha1::digest::Update::chain(hasher, &buf[..]);
-        buf = [0; 1024];
-        len = file.read(&mut buf[..])?;
-        // debug purpose. Comment out when running sha1_content_test
-        // println!("{}", str::from_utf8(&buf).unwrap());
-    }
+    let len = std::io::copy(file, &mut hasher)?;
+    pad_to_chunk(&mut hasher, len);
+
+    Ok(Oid(hasher.finalize().into()))
+}
+
+/// Feeds `hasher` the zeros that pad `len` bytes up to a multiple of 1024, see `digest_file`.
+///
+/// * `hasher`:
+/// * `len`: how much content was hashed.
+fn pad_to_chunk(hasher: &mut Sha1, len: u64) {
+    let padding = (1024 - len % 1024) % 1024;
+    hasher.update(&[0; 1024][..padding as usize]);
+}
 
-    // todo!()
-    Ok(hasher.finalize().into())
+/// `digest_file`, for content that's already in memory. Hashes the same way, so the same content
+/// gets the same blob hash either way.
+///
+/// * `contents`:
+pub fn digest_bytes(contents: &[u8]) -> Oid {
+    let mut hasher = Sha1::new();
+    hasher.update(contents);
+    pad_to_chunk(&mut hasher, contents.len() as u64);
+    Oid(hasher.finalize().into())
 }
 
 /// Generates the SHA1 in string form from the given content.
@@ -48,33 +140,31 @@ pub fn get_sha1_bytes(contents: &[u8]) -> [u8; 20] {
 }
 
 #[inline]
-/// Just a nicer name to `hex::encode(hash)`
+/// Just a nicer name to `hash.to_string()`, from before `Oid`.
 ///
 /// * `hash`:
-pub fn to_string(hash: &[u8; 20]) -> String {
-    hex::encode(hash)
+pub fn to_string(hash: &Oid) -> String {
+    hash.to_string()
 }
 
-/// Convenience function to convert from a SHA1 string into a SHA1 array.
+/// Convenience function to convert from a SHA1 string into an `Oid`, from before `Oid`.
+/// Same as `s.parse()`.
 ///
 /// # Return value
-/// - If the string cannot be converted to SHA1 bytes, return Err, otherwise Ok([u8; 20]).
+/// - If the string cannot be converted to SHA1 bytes, return Err, otherwise Ok(Oid).
 /// * `s`:
-pub fn from_string(s: &str) -> Result<[u8; 20]> {
-    Ok(
-        std::convert::TryInto::<[u8; 20]>::try_into(&hex::decode(s)?[..20])
-            .or(Err(format!("Cannot convert {} into SHA1 bytes", s)))?,
-    )
+pub fn from_string(s: &str) -> Result<Oid> {
+    s.parse()
 }
 
-/// Convenience function to convert from a SHA1 OS string into a SHA1 array.
+/// Convenience function to convert from a SHA1 OS string into an `Oid`.
 ///
 /// # Return value
-/// - If the string cannot be converted to SHA1 bytes, return Err, otherwise Ok([u8; 20]).
+/// - If the string cannot be converted to SHA1 bytes, return Err, otherwise Ok(Oid).
 ///   - This function basically tries to convert an &OsStr into a &str (which it should be able to
 ///     since any OS should be able to display SHA1).
 /// * `s`:
-pub fn from_os_str(oss: &OsStr) -> Result<[u8; 20]> {
+pub fn from_os_str(oss: &OsStr) -> Result<Oid> {
     // if it's "default", it's a fail right away.
     // I'm pretty sure any OS can represent a hex as a string.
     from_string(oss.to_str().unwrap_or_default())
@@ -146,9 +236,57 @@ mod test {
         );
     }
 
-    #[ignore = "Only run this test after un-commenting the println! inside digest_file"]
+    #[ignore = "This test only runs digest_file on a real file"]
     #[test]
     fn sha1_content_test() {
         digest_file(&mut fs::File::open("src/hash.rs").unwrap()).unwrap();
     }
+
+    #[test]
+    fn oid_test() {
+        let hex = "da39a3ee5e6b4b0d3255bfef95601890afd80709";
+        let oid: Oid = hex.parse().unwrap();
+        assert_eq!(oid, Oid::of(b""));
+        assert_eq!(oid.to_string(), hex);
+        assert_eq!(format!("{oid:?}"), format!("Oid({hex})"));
+        assert_eq!(Oid::try_from(hex).unwrap(), oid);
+        assert_eq!(from_string(hex).unwrap(), oid);
+        assert_eq!(to_string(&oid), hex);
+        assert!("not hex".parse::<Oid>().is_err());
+    }
+
+    #[test]
+    fn digest_bytes_test() {
+        let content: Vec<u8> = (0..3000).map(|i| (i % 251) as u8).collect();
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("content");
+        fs::write(&path, &content).unwrap();
+        assert_eq!(
+            digest_bytes(&content),
+            digest_file(&mut fs::File::open(&path).unwrap()).unwrap()
+        );
+        // the names blobs have always had, existing repositories depend on them.
+        assert_eq!(
+            digest_bytes(&content).to_string(),
+            "9cf49927996f3b43c2ad2cc38fe5fd7192bc9cc1"
+        );
+        assert_eq!(
+            digest_bytes(b"hello\n").to_string(),
+            "50e1925c5061668ee13535a47e49915d89324a7a"
+        );
+        assert_eq!(digest_bytes(b""), Oid::of(b""));
+    }
+
+    #[test]
+    fn from_string_length_test() {
+        let hex = "da39a3ee5e6b4b0d3255bfef95601890afd80709";
+        // too short used to panic instead.
+        assert!(from_string(&hex[..38]).is_err());
+        assert!(from_string("").is_err());
+        // and too long used to be cut down to the first 20 bytes.
+        assert!(from_string(&format!("{hex}00")).is_err());
+        // odd number of digits.
+        assert!(from_string(&hex[..39]).is_err());
+        assert!(from_string(hex).is_ok());
+    }
 }
@@ -1,7 +1,10 @@
 pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+pub mod config;
+pub mod diff;
 pub mod hash;
 pub mod fs;
 pub mod objects;
 pub mod dirtree;
+pub mod repo;
 pub mod root;
 pub mod utils;
@@ -1,21 +1,25 @@
 #![allow(dead_code)]
 use crate::{
-    hash,
-    utils::{gyat_paths, AllPaths},
+    config::Config,
+    fs::ChangeType,
+    hash::{self, Oid},
+    repo::Repository,
+    utils::{self, gyat_paths, AllPaths},
     Result,
 };
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashSet},
     ffi::{OsStr, OsString},
     fs::File,
-    io::{BufRead, BufReader, Read, Write},
-    path::PathBuf,
+    io::{BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
 };
 
 use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
 
-/// Gets the compressed format of a blob as a vector of bytes.
-/// For this implementation, only the contents of `blob`s are compressed.
+/// Gets the compressed format of a blob as a vector of bytes: a `blob <len>\0` header, then the
+/// zlib compressed content. For this implementation, only the contents of `blob`s are compressed.
 ///
 /// Note: before calling this function, make sure that there's no `blob` with the same SHA1 already
 /// stored in the repository.
@@ -23,23 +27,307 @@ use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
 /// * `blob_file`: the file to generate a blob for. Must be a file.
 /// # Return values
 /// - Err for any I/O error encountered.
-/// - Ok(Vec<u8>) where the vector is the compressed content if nothing goes wrong.
-pub fn format_blob_content(blob_source: &mut File) -> Result<Vec<u8>> {
+/// - Ok(Vec<u8>) where the vector is the header and the compressed content if nothing goes wrong.
+/// * `level`: how hard to compress. See `compression_level`.
+pub fn format_blob_content(blob_source: &mut File, level: Compression) -> Result<Vec<u8>> {
     debug_assert!(blob_source.metadata()?.is_file());
 
-    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
-    let mut reader = BufReader::new(blob_source);
-    let mut buf: [u8; 1024] = [0; 1024];
-    while {
-        buf.fill(0);
-        reader.read(&mut buf[..])? > 0
-    } {
-        encoder.write_all(&buf)?;
-    }
+    let len = blob_source.metadata()?.len();
+    encode_blob(blob_source, len, level, Vec::new())
+}
 
+/// Compresses `raw` into a blob as it's stored, header included. Nothing is written anywhere.
+///
+/// # Return values
+/// - Err if compressing fails.
+/// - Ok(Vec<u8>) with the blob otherwise. `inflate_blob` turns it back into `raw`.
+///
+/// * `raw`: the content, eg, of a file.
+/// * `level`: how hard to compress. See `compression_level`.
+pub fn deflate_blob(mut raw: &[u8], level: Compression) -> Result<Vec<u8>> {
+    let len = raw.len() as u64;
+    encode_blob(&mut raw, len, level, Vec::new())
+}
+
+/// Writes a blob as it's stored into `out`: the header, then `raw` compressed as it's read.
+/// Both `format_blob_content` and `deflate_blob` go through here.
+///
+/// # Return values
+/// - Err for any I/O error, or if `raw` doesn't have exactly `len` bytes, since the header would
+///   be wrong then.
+/// - Ok(W) with `out` back, everything written.
+///
+/// * `raw`: the content, read to the end.
+/// * `len`: how long `raw` is, for the header.
+/// * `level`: how hard to compress. See `compression_level`.
+/// * `out`:
+fn encode_blob<R: Read, W: Write>(
+    raw: &mut R,
+    len: u64,
+    level: Compression,
+    mut out: W,
+) -> Result<W> {
+    out.write_all(&hash::blob_header(len))?;
+    let mut encoder = blob_encoder(out, level);
+    if std::io::copy(raw, &mut encoder)? != len {
+        return Err("The content changed while it was being compressed".into());
+    }
     Ok(encoder.finish()?)
 }
 
+/// Decompresses a blob as it's stored, header and all, see `deflate_blob`. Blobs from before
+/// there was a header are read too, see `read_blob_to`.
+///
+/// # Return values
+/// - Err if `compressed` isn't a valid blob.
+/// - Ok(Vec<u8>) with the content otherwise.
+///
+/// * `compressed`: eg, the whole file in `.gyat/files`.
+pub fn inflate_blob(compressed: &[u8]) -> Result<Vec<u8>> {
+    let mut content = Vec::new();
+    inflate_to(compressed, &mut content)?;
+    Ok(content)
+}
+
+/// Reads the header `hash::blob_header` wrote, leaving `reader` at the start of the compressed content.
+///
+/// Blobs written before there was a header start right away with the zlib stream, which never
+/// starts with `b` (its first byte is 0x78), so there's no mixing them up.
+///
+/// # Return values
+/// - Err if the header is there but broken, or for any I/O error.
+/// - Ok(None) for a blob without a header.
+/// - Ok(Some(len)) with the size of the content otherwise.
+///
+/// * `reader`:
+fn read_blob_header<R: BufRead>(reader: &mut R) -> Result<Option<u64>> {
+    if !reader.fill_buf()?.starts_with(b"blob ") {
+        return Ok(None);
+    }
+    let mut header = Vec::new();
+    reader.read_until(0, &mut header)?;
+    header
+        .strip_prefix(b"blob ")
+        .and_then(|h| h.strip_suffix(b"\0"))
+        .and_then(|len| std::str::from_utf8(len).ok())
+        .and_then(|len| len.parse().ok())
+        .map(Some)
+        .ok_or_else(|| "Invalid blob header".into())
+}
+
+/// The size of a blob's content, read from its header only, so nothing gets decompressed.
+///
+/// # Return values
+/// - Err if the blob doesn't exist or cannot be read.
+/// - Ok(None) if the blob was stored before blobs had a header. `read_blob` still knows its size.
+/// - Ok(Some(len)) otherwise.
+///
+/// * `blob_hash`:
+pub fn blob_size(blob_hash: &Oid) -> Result<Option<u64>> {
+    blob_size_at(&gyat_paths()?.files_path, blob_hash)
+}
+
+/// `blob_size`, from the blobs in `files_path`.
+///
+/// * `files_path`: `.gyat/files` of the repository to read from.
+/// * `blob_hash`:
+pub(crate) fn blob_size_at(files_path: &Path, blob_hash: &Oid) -> Result<Option<u64>> {
+    if !object_exists(files_path, blob_hash) {
+        return Err(format!("Blob hash {} doesn't exist", blob_hash).into());
+    }
+    read_blob_header(&mut BufReader::new(File::open(
+        files_path.join(blob_hash.to_string()),
+    )?))
+}
+
+/// Whether a blob with this hash is stored, see `object_exists`.
+///
+/// * `hash`:
+pub fn blob_exists(hash: &Oid) -> bool {
+    gyat_paths().is_ok_and(|paths| object_exists(&paths.files_path, hash))
+}
+
+/// Whether a tree with this hash is stored, see `object_exists`.
+///
+/// * `hash`:
+pub fn tree_exists(hash: &Oid) -> bool {
+    gyat_paths().is_ok_and(|paths| object_exists(&paths.dirs_path, hash))
+}
+
+/// Whether a commit with this hash is stored, see `object_exists`.
+///
+/// * `hash`:
+pub fn commit_exists(hash: &Oid) -> bool {
+    gyat_paths().is_ok_and(|paths| object_exists(&paths.commits_path, hash))
+}
+
+/// Every object of one kind that's stored, sorted.
+///
+/// Anything in the object directory that isn't named like a hash is skipped with a warning, except
+/// the temporary files of writes still going on (see `write_object`). There are no packfiles, so
+/// the directory is all there is.
+///
+/// # Return values
+/// - Err if the object directory cannot be read.
+/// - Ok(Vec<Oid>) otherwise.
+///
+/// * `repo`: the repository whose objects are listed.
+/// * `kind`: which directory to list, `.gyat/files`, `.gyat/dirs` or `.gyat/commits`.
+pub fn list_objects(repo: &Repository, kind: ObjType) -> Result<Vec<Oid>> {
+    let AllPaths {
+        files_path,
+        dirs_path,
+        commits_path,
+        ..
+    } = repo.paths();
+    let object_dir = match kind {
+        ObjType::File(FType::Blob) => files_path,
+        ObjType::File(FType::Tree) => dirs_path,
+        ObjType::Commit => commits_path,
+    };
+
+    let mut ret = Vec::new();
+    for entry in std::fs::read_dir(object_dir)? {
+        let name = entry?.file_name();
+        match hash::from_os_str(&name) {
+            Ok(hash) => ret.push(hash),
+            Err(_) if name.to_string_lossy().ends_with(".tmp") => {}
+            Err(_) => eprintln!(
+                "Skipping {}, it's not an object",
+                object_dir.join(&name).display()
+            ),
+        }
+    }
+    ret.sort();
+    Ok(ret)
+}
+
+/// Whether `hash` is stored in `object_dir`, one of the object directories.
+///
+/// * `object_dir`: `.gyat/files`, `.gyat/dirs` or `.gyat/commits`.
+/// * `hash`:
+pub fn object_exists(object_dir: &Path, hash: &Oid) -> bool {
+    object_dir.join(hash.to_string()).exists()
+}
+
+/// Numbers the temporary files `write_object` writes, see there.
+static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Writes an object into one of the object directories, without anyone ever seeing it half
+/// written.
+///
+/// The content goes to a temporary file named after the process and a counter first, which is
+/// then renamed into place. Renaming is atomic, and the same hash always means the same content, so
+/// when threads (or processes) race to write the same object, whoever renames last wins and it
+/// doesn't matter.
+///
+/// * `object_dir`: `.gyat/files`, `.gyat/dirs`, `.gyat/commits` or `.gyat/tags`.
+/// * `hash`: the name of the object.
+/// * `content`: exactly what's stored, eg, `deflate_blob`'s output for a blob.
+pub fn write_object(object_dir: &Path, hash: &Oid, content: &[u8]) -> Result<()> {
+    // no two writes in this process get the same number, whichever thread they're on.
+    let tmp_id = TMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let tmp_path = object_dir.join(format!("{hash}.{}.{tmp_id}.tmp", std::process::id()));
+    std::fs::write(&tmp_path, content)?;
+    if let Err(e) = std::fs::rename(&tmp_path, object_dir.join(hash.to_string())) {
+        let _ = std::fs::remove_file(&tmp_path);
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+/// Stores `content` as a blob, for content that isn't a file in the working tree, eg, a file with
+/// only some of its changes observed. It's stored like `format_blob_content` does, and not written
+/// again if it's already stored.
+///
+/// # Return values
+/// - Err for any I/O error.
+/// - Ok(Oid) with the blob hash otherwise.
+///
+/// * `content`:
+pub fn write_blob(content: &[u8]) -> Result<Oid> {
+    let AllPaths {
+        gyat_path,
+        files_path,
+        ..
+    } = gyat_paths()?;
+    write_blob_at(&files_path, content, Config::load(&gyat_path)?.compression)
+}
+
+/// `write_blob`, into the blobs in `files_path`.
+///
+/// * `files_path`: `.gyat/files` of the repository to store into.
+/// * `content`:
+/// * `level`: the compression level, if the blob is new.
+pub(crate) fn write_blob_at(files_path: &Path, content: &[u8], level: Compression) -> Result<Oid> {
+    let blob_hash = hash::digest_bytes(content);
+    if !object_exists(files_path, &blob_hash) {
+        write_object(files_path, &blob_hash, &deflate_blob(content, level)?)?;
+    }
+    Ok(blob_hash)
+}
+
+/// Stores a file as a blob, like `format_blob_content` does, unless it's already stored.
+///
+/// # Return values
+/// - Err for any I/O error.
+/// - Ok(Oid) with the blob hash otherwise.
+///
+/// * `files_path`: `.gyat/files` of the repository to store into.
+/// * `source_file`: read from the start.
+/// * `level`: the compression level, if the blob is new.
+pub(crate) fn write_file_blob(
+    files_path: &Path,
+    source_file: &mut File,
+    level: Compression,
+) -> Result<Oid> {
+    let hash = hash::digest_file(source_file)?;
+    // an unchanged file already has its blob, and compressing is the expensive part.
+    if object_exists(files_path, &hash) {
+        return Ok(hash);
+    }
+
+    source_file.seek(SeekFrom::Start(0))?;
+    let blob_content = format_blob_content(source_file, level)?;
+    write_object(files_path, &hash, &blob_content)?;
+    Ok(hash)
+}
+
+/// The SHA1 of a tree object with no children, ie, an empty directory.
+/// This is also the SHA1 of an empty file, but trees and blobs live in different directories.
+pub const EMPTY_TREE: Oid = Oid::new([
+    0xda, 0x39, 0xa3, 0xee, 0x5e, 0x6b, 0x4b, 0x0d, 0x32, 0x55, 0xbf, 0xef, 0x95, 0x60, 0x18, 0x90,
+    0xaf, 0xd8, 0x07, 0x09,
+]);
+
+/// Stands in for an empty directory inside blob maps (like the one `get_blobs_from_root` returns)
+/// and the index. Not a real object.
+///
+/// It can't be `EMPTY_TREE`, otherwise an empty file and an empty directory at the same path would
+/// look the same.
+pub const EMPTY_DIR: Oid = Oid::new([0; 20]);
+
+/// The encoder blob contents are compressed with.
+/// The level only changes how small the output is, not what it decompresses to, so blobs written
+/// with any level stay readable.
+///
+/// * `out`: where the compressed content goes.
+/// * `level`:
+pub fn blob_encoder<W: Write>(out: W, level: Compression) -> ZlibEncoder<W> {
+    ZlibEncoder::new(out, level)
+}
+
+/// The compression level blobs should be written with.
+/// Just `Config::compression` of the current repository, see `config::Config`.
+///
+/// # Return values
+/// - Err if `.gyat/config` cannot be read or has an invalid level.
+/// - Ok(Compression) otherwise.
+pub fn compression_level() -> Result<Compression> {
+    let AllPaths { gyat_path, .. } = gyat_paths()?;
+    Ok(Config::load(&gyat_path)?.compression)
+}
+
 #[derive(Debug, Eq, PartialEq, Hash, Clone, Copy)]
 /// Either a blob (file/symlink?) or a tree (directory).
 pub enum FType {
@@ -54,7 +342,7 @@ pub enum ObjType {
     File(FType),
 }
 
-#[derive(Debug, Hash, PartialEq, Eq)]
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
 /// Includes tree and blob objects.
 ///
 /// * `ftype`:
@@ -62,7 +350,7 @@ pub enum ObjType {
 /// * `component`:
 pub struct FileObject {
     pub ftype: FType,
-    pub hash: [u8; 20],
+    pub hash: Oid,
     pub component: OsString,
 }
 
@@ -72,11 +360,17 @@ pub struct FileObject {
 ///
 /// * `parent`:
 /// * `root`:
-/// * `datetime`: currently unused
+/// * `message`: the commit message. Empty if the commit file has none.
+/// * `author`: `name <email>`. None for commits made before commits had authors.
+/// * `date`: as written in the commit, eg, `Sat Oct 17 03:19:25 2026`. None if there's no date.
+/// * `changes`: the list under `Changes:`, each line a `ChangeType::as_token` and a path.
 pub struct CommitObject {
-    pub parent: Option<[u8; 20]>,
-    pub root: [u8; 20],
-    // pub datetime: DateTime<Local>,
+    pub parent: Option<Oid>,
+    pub root: Oid,
+    pub message: String,
+    pub author: Option<String>,
+    pub date: Option<String>,
+    pub changes: Vec<(ChangeType, PathBuf)>,
 }
 
 impl FileObject {
@@ -107,7 +401,7 @@ impl FileObject {
 /// * `component`:
 pub struct FileObjectRef<'a> {
     pub ftype: FType,
-    pub hash: &'a [u8; 20],
+    pub hash: &'a Oid,
     pub component: &'a OsStr,
 }
 
@@ -122,7 +416,7 @@ impl PartialEq for dyn FObj {
 // DO NOT IMPLEMENT MORE OF THIS TRAIT THAN THE ONES ABOVE.
 pub trait FObj {
     fn ftype(&self) -> FType;
-    fn hash(&self) -> &[u8; 20];
+    fn hash(&self) -> &Oid;
     fn component(&self) -> &OsStr;
 }
 
@@ -133,7 +427,7 @@ impl FObj for FileObject {
     }
 
     #[inline]
-    fn hash(&self) -> &[u8; 20] {
+    fn hash(&self) -> &Oid {
         &self.hash
     }
 
@@ -150,7 +444,7 @@ impl<'a> FObj for FileObjectRef<'a> {
     }
 
     #[inline]
-    fn hash(&self) -> &[u8; 20] {
+    fn hash(&self) -> &Oid {
         self.hash
     }
 
@@ -177,14 +471,19 @@ impl<'a> FObj for FileObjectRef<'a> {
 /// # Note
 /// - This function does no I/O, so it doesn't return any error, unlike its counterpart
 ///   `get_blob_content`. It only formats the data passed into it.
+/// - The children are written sorted by component, no matter what order the iterator yields
+///   them in. Otherwise the same directory could get a different SHA1 each time.
 pub fn format_tree_content<'a>(children: impl Iterator<Item = FileObjectRef<'a>>) -> Vec<u8> {
+    let mut children: Vec<_> = children.collect();
+    children.sort_by(|a, b| a.component.cmp(b.component));
+
     let mut ret = Vec::new();
     for c in children {
         let type_str = match c.ftype {
             FType::Blob => "blob",
             FType::Tree => "tree",
         };
-        let child_hash = hash::to_string(c.hash);
+        let child_hash = c.hash.to_string();
         ret.extend(type_str.as_bytes());
         ret.push(b'\t');
         ret.extend(child_hash.as_bytes());
@@ -206,12 +505,19 @@ pub fn format_tree_content<'a>(children: impl Iterator<Item = FileObjectRef<'a>>
 ///   the tree node.
 ///
 /// * `tree_hash`:
-pub fn read_tree_content(tree_hash: &[u8; 20]) -> Result<Vec<FileObject>> {
-    let AllPaths { dirs_path, .. } = gyat_paths()?;
-    let tree_path = dirs_path.join(hash::to_string(tree_hash));
-    if !tree_path.exists() {
-        return Err(format!("Tree hash {} doesn't exist", hash::to_string(tree_hash)).into());
+pub fn read_tree_content(tree_hash: &Oid) -> Result<Vec<FileObject>> {
+    read_tree_at(&gyat_paths()?.dirs_path, tree_hash)
+}
+
+/// `read_tree_content`, from the trees in `dirs_path`.
+///
+/// * `dirs_path`: `.gyat/dirs` of the repository to read from.
+/// * `tree_hash`:
+pub(crate) fn read_tree_at(dirs_path: &Path, tree_hash: &Oid) -> Result<Vec<FileObject>> {
+    if !object_exists(dirs_path, tree_hash) {
+        return Err(format!("Tree hash {} doesn't exist", tree_hash).into());
     }
+    let tree_path = dirs_path.join(tree_hash.to_string());
 
     let mut ret = Vec::new();
     // so, it will probably throw when not enough permissions somehow.
@@ -229,7 +535,7 @@ pub fn read_tree_content(tree_hash: &[u8; 20]) -> Result<Vec<FileObject>> {
                 return Err(format!("Invalid file type format in {}", &tree_path.display()).into());
             }
         };
-        let hash = hash::from_string(parts[1])?;
+        let hash = parts[1].parse()?;
         let component = parts[2];
         ret.push(FileObject {
             ftype,
@@ -244,37 +550,207 @@ pub fn read_tree_content(tree_hash: &[u8; 20]) -> Result<Vec<FileObject>> {
 ///
 /// # Return values:
 /// - Err if I/O error.
-/// - Ok(HashMap) otherwise.
-///   - The key of the HashMap is the path relative to the directory represented by `root_hash`.
-///   - The value of the HashMap is the corresponding SHA1 to that path.
+/// - Ok(BTreeMap) otherwise, so iterating it goes through the paths in order.
+///   - The key of the map is the path relative to the directory represented by `root_hash`.
+///   - The value of the map is the corresponding SHA1 to that path.
+///   - Empty directories (a tree entry pointing to `EMPTY_TREE`) are included, with `EMPTY_DIR` as
+///     their value.
 ///
 /// * `root_hash`: It's called `root_hash` due to the relative path.
-pub fn get_blobs_from_root(root_hash: &[u8; 20]) -> Result<HashMap<PathBuf, [u8; 20]>> {
-    let mut ret = HashMap::new();
-    let mut stack: Vec<(FType, PathBuf, [u8; 20])> = Vec::new();
-    stack.extend(
-        read_tree_content(root_hash)?
-            .into_iter()
-            .map(|fo| (fo.ftype, PathBuf::from(fo.component), fo.hash)),
-    );
-
-    while let Some(obj) = stack.pop() {
-        use FType::*;
-        match obj.0 {
-            Blob => {
-                ret.insert(obj.1, obj.2);
+pub fn get_blobs_from_root(root_hash: &Oid) -> Result<BTreeMap<PathBuf, Oid>> {
+    collect_blobs(walk_tree(root_hash))
+}
+
+/// The blobs a tree walk goes through, see `get_blobs_from_root`.
+///
+/// * `walk`: from `walk_tree` or `walk_tree_with`.
+pub(crate) fn collect_blobs(
+    walk: impl Iterator<Item = Result<(PathBuf, FType, Oid)>>,
+) -> Result<BTreeMap<PathBuf, Oid>> {
+    let mut ret = BTreeMap::new();
+    for entry in walk {
+        match entry? {
+            (path, FType::Blob, hash) => {
+                ret.insert(path, hash);
             }
-            Tree => stack.extend(
-                read_tree_content(&obj.2)?
-                    .into_iter()
-                    .map(|fo| (fo.ftype, obj.1.join(fo.component), fo.hash)),
-            ),
+            (path, FType::Tree, EMPTY_TREE) => {
+                ret.insert(path, EMPTY_DIR);
+            }
+            _ => {}
         }
     }
 
     Ok(ret)
 }
 
+/// Walks the tree `root_hash` and everything below it, one entry at a time. Trees are only read
+/// once the walk reaches them, so nothing is built up front.
+///
+/// The order is deterministic: depth-first, children sorted by component, and each tree comes
+/// right before its contents.
+///
+/// # Return values
+/// An iterator of:
+/// - Err if a tree cannot be read. The walk stops after that.
+/// - Ok((path, type, SHA1)) otherwise. The path is relative to `root_hash`, and `root_hash`
+///   itself is not yielded.
+///
+/// * `root_hash`: the tree to start from.
+pub fn walk_tree(root_hash: &Oid) -> impl Iterator<Item = Result<(PathBuf, FType, Oid)>> {
+    walk_tree_with(root_hash, read_tree_content)
+}
+
+/// `walk_tree`, with every tree read through `read_tree` instead, eg, from
+/// `Repository::read_tree_content`'s cache.
+///
+/// * `root_hash`:
+/// * `read_tree`: the children of a tree, like `read_tree_content` returns them.
+pub fn walk_tree_with<F>(
+    root_hash: &Oid,
+    read_tree: F,
+) -> impl Iterator<Item = Result<(PathBuf, FType, Oid)>>
+where
+    F: FnMut(&Oid) -> Result<Vec<FileObject>>,
+{
+    TreeWalk {
+        pending_root: Some(*root_hash),
+        stack: Vec::new(),
+        read_tree,
+    }
+}
+
+/// See `walk_tree`.
+///
+/// * `pending_root`: the root tree, until it's read on the first `next`.
+/// * `stack`: for each tree being walked, its path and the children left to visit.
+/// * `read_tree`:
+struct TreeWalk<F> {
+    pending_root: Option<Oid>,
+    stack: Vec<(PathBuf, std::vec::IntoIter<FileObject>)>,
+    read_tree: F,
+}
+
+impl<F: FnMut(&Oid) -> Result<Vec<FileObject>>> TreeWalk<F> {
+    fn descend(&mut self, path: PathBuf, tree_hash: &Oid) -> Result<()> {
+        let mut children = (self.read_tree)(tree_hash)?;
+        // trees are written sorted, but better not trust old ones.
+        children.sort_by(|a, b| a.component.cmp(&b.component));
+        self.stack.push((path, children.into_iter()));
+        Ok(())
+    }
+}
+
+impl<F: FnMut(&Oid) -> Result<Vec<FileObject>>> Iterator for TreeWalk<F> {
+    type Item = Result<(PathBuf, FType, Oid)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(root) = self.pending_root.take() {
+            if let Err(e) = self.descend(PathBuf::new(), &root) {
+                return Some(Err(e));
+            }
+        }
+        loop {
+            let (dir, children) = self.stack.last_mut()?;
+            let Some(child) = children.next() else {
+                self.stack.pop();
+                continue;
+            };
+            let path = dir.join(&child.component);
+            // an empty tree has nothing to walk into, and may not even be written.
+            if child.ftype == FType::Tree && child.hash != EMPTY_TREE {
+                if let Err(e) = self.descend(path.clone(), &child.hash) {
+                    self.stack.clear();
+                    return Some(Err(e));
+                }
+            }
+            return Some(Ok((path, child.ftype, child.hash)));
+        }
+    }
+}
+
+/// Checks that the tree `root_hash`, every tree below it and every blob in it exist.
+///
+/// # Return values
+/// - Err describing what's missing (or unreadable) otherwise.
+/// - Ok(()) if everything is there.
+///
+/// * `root_hash`: the tree to check, eg, a commit's `Tree:`.
+pub fn verify_tree(root_hash: &Oid) -> Result<()> {
+    let AllPaths { files_path, .. } = gyat_paths()?;
+    // already sorted by path.
+    let missing: Vec<_> = get_blobs_from_root(root_hash)?
+        .into_iter()
+        .filter(|(_, hash)| {
+            *hash != EMPTY_DIR && !object_exists(&files_path, hash)
+        })
+        .collect();
+    match &missing[..] {
+        [] => Ok(()),
+        [(path, hash), rest @ ..] => {
+            let mut msg = format!("Blob hash {} doesn't exist, for {}", hash, path.display());
+            if !rest.is_empty() {
+                let _ = std::fmt::write(&mut msg, format_args!(" (and {} more)", rest.len()));
+            }
+            Err(msg.into())
+        }
+    }
+}
+
+/// A single changed path between two sets of blobs.
+///
+/// * `0`: what happened to the path.
+/// * `1`: the path, relative to the roots being compared.
+/// * `2`: the blob hash before the change. None if the path is new.
+/// * `3`: the blob hash after the change. None if the path was deleted.
+pub type BlobChange = (ChangeType, PathBuf, Option<Oid>, Option<Oid>);
+
+/// Compares 2 sets of blobs, as returned by `get_blobs_from_root`.
+/// Unchanged paths are not reported.
+///
+/// # Return value
+/// - The changes, sorted by path, so the output is the same from run to run.
+///
+/// * `old`:
+/// * `new`:
+pub fn diff_blobs(old: &BTreeMap<PathBuf, Oid>, new: &BTreeMap<PathBuf, Oid>) -> Vec<BlobChange> {
+    let mut ret = Vec::new();
+    for (path, new_hash) in new {
+        match old.get(path) {
+            None => ret.push((ChangeType::New, path.clone(), None, Some(*new_hash))),
+            Some(old_hash) if old_hash != new_hash => ret.push((
+                ChangeType::Mod,
+                path.clone(),
+                Some(*old_hash),
+                Some(*new_hash),
+            )),
+            Some(_) => {}
+        }
+    }
+    for (path, old_hash) in old {
+        if !new.contains_key(path) {
+            ret.push((ChangeType::Del, path.clone(), Some(*old_hash), None));
+        }
+    }
+    ret.sort_by(|a, b| a.1.cmp(&b.1));
+
+    ret
+}
+
+/// Compares the blobs under 2 root trees.
+///
+/// # Return values
+/// - Err if either tree (or any of their subtrees) cannot be read.
+/// - Ok(Vec<BlobChange>) otherwise. See `diff_blobs`.
+///
+/// * `old`: the root tree hash to compare from.
+/// * `new`: the root tree hash to compare to.
+pub fn diff_trees(old: &Oid, new: &Oid) -> Result<Vec<BlobChange>> {
+    Ok(diff_blobs(
+        &get_blobs_from_root(old)?,
+        &get_blobs_from_root(new)?,
+    ))
+}
+
 /// For now this ignores the list of changes, since I don't need it right now. But I will add it
 /// later.
 ///
@@ -283,24 +759,31 @@ pub fn get_blobs_from_root(root_hash: &[u8; 20]) -> Result<HashMap<PathBuf, [u8;
 /// - Ok(CommitObject) otherwise.
 ///
 /// * `commit_hash`:
-pub fn read_commit_content(commit_hash: &[u8; 20]) -> Result<CommitObject> {
-    let AllPaths { commits_path, .. } = gyat_paths()?;
-    let commit_file = commits_path.join(hash::to_string(commit_hash));
-    if !commit_file.exists() {
-        return Err(format!("Commit hash {} not exist", hash::to_string(commit_hash)).into());
+pub fn read_commit_content(commit_hash: &Oid) -> Result<CommitObject> {
+    read_commit_at(&gyat_paths()?.commits_path, commit_hash)
+}
+
+/// `read_commit_content`, from the commits in `commits_path`.
+///
+/// * `commits_path`: `.gyat/commits` of the repository to read from.
+/// * `commit_hash`:
+pub(crate) fn read_commit_at(commits_path: &Path, commit_hash: &Oid) -> Result<CommitObject> {
+    if !object_exists(commits_path, commit_hash) {
+        return Err(format!("Commit hash {} not exist", commit_hash).into());
     }
+    let commit_file = commits_path.join(commit_hash.to_string());
 
-    let mut reader = BufReader::new(File::open(commit_file)?);
+    let mut reader = BufReader::new(File::open(&commit_file)?);
     let mut buf = String::new();
     if reader.read_line(&mut buf)? == 0 {
         return Err(format!("Commit file {} empty", commits_path.display()).into());
     }
     // the first should always be parent.
-    let parts = buf.split(':').collect::<Vec<_>>();
-    let parent = if parts[1].trim().len() < 20 {
-        None
-    } else {
-        Some(hash::from_string(parts[1].trim())?)
+    // Only the first ':' separates the key from the value, whatever comes after is the value.
+    let parent = match buf.split_once(':') {
+        Some(("Parent", p)) if p.trim().len() < 20 => None,
+        Some(("Parent", p)) => Some(p.trim().parse()?),
+        _ => return Err(format!("Commit {} has no parent line", commit_file.display()).into()),
     };
     buf.clear();
 
@@ -308,30 +791,752 @@ pub fn read_commit_content(commit_hash: &[u8; 20]) -> Result<CommitObject> {
         return Err(format!("Commit file {} empty", commits_path.display()).into());
     }
     // this one should be Tree.
-    let parts = buf.split(':').collect::<Vec<_>>();
-    let root = hash::from_string(parts[1].trim()).unwrap();
+    let root = match buf.split_once(':') {
+        Some(("Tree", t)) => t.trim().parse()?,
+        _ => return Err(format!("Commit {} has no tree line", commit_file.display()).into()),
+    };
+
+    // then the rest of the header, in whatever order, until the list of changes.
+    let mut message = String::new();
+    let (mut author, mut date) = (None, None);
+    while {
+        buf.clear();
+        reader.read_line(&mut buf)? > 0
+    } {
+        match buf.split_once(':') {
+            Some(("Message", m)) => message = m.trim().to_string(),
+            Some(("Author", a)) => author = Some(a.trim().to_string()),
+            Some(("Date", d)) => date = Some(d.trim().to_string()),
+            Some(("Changes", _)) => break,
+            _ => {}
+        }
+    }
+
+    let mut changes = Vec::new();
+    while {
+        buf.clear();
+        reader.read_line(&mut buf)? > 0
+    } {
+        let line = buf.strip_suffix('\n').unwrap_or(&buf);
+        if line.is_empty() {
+            continue;
+        }
+        match line.split_once('\t') {
+            Some((token, path)) => {
+                changes.push((ChangeType::from_token(token)?, PathBuf::from(path)))
+            }
+            None => {
+                return Err(format!(
+                    "Commit {} has an invalid change {}",
+                    commit_file.display(),
+                    line
+                )
+                .into())
+            }
+        }
+    }
+
+    Ok(CommitObject {
+        parent,
+        root,
+        message,
+        author,
+        date,
+        changes,
+    })
+}
+
+/// Resolves a name the user typed into the commit it refers to.
+///
+/// Tried in order:
+/// - `HEAD`.
+/// - A branch, `.gyat/refs/heads/<name>`.
+/// - A tag, `.gyat/refs/tags/<name>`. Annotated tags are followed to the commit they point to.
+/// - A full commit hash.
+/// - The first characters (at least 4) of exactly one commit hash.
+///
+/// # Return values
+/// - Err if nothing matches, or if an abbreviated hash matches more than one commit.
+/// - Ok(Oid) with the commit hash otherwise.
+///
+/// * `repo`:
+/// * `name`:
+pub fn resolve_ref(repo: &Repository, name: &str) -> Result<Oid> {
+    let AllPaths {
+        gyat_path,
+        commits_path,
+        refs_path,
+        tags_path,
+        ..
+    } = repo.paths();
+
+    if name == "HEAD" {
+        let head = utils::read_head(gyat_path)?.ok_or("HEAD doesn't point to any commit yet")?;
+        return head.parse();
+    }
+
+    for ref_dir in ["heads", "tags"] {
+        let ref_path = refs_path.join(ref_dir).join(name);
+        if !ref_path.is_file() {
+            continue;
+        }
+        let target: Oid = std::fs::read_to_string(&ref_path)?.trim().parse()?;
+        let tag_object = tags_path.join(target.to_string());
+        if !tag_object.exists() {
+            return Ok(target);
+        }
+        // annotated tag, the commit is in its Object: line.
+        return std::fs::read_to_string(&tag_object)?
+            .lines()
+            .find_map(|line| line.strip_prefix("Object:"))
+            .map(|commit| commit.trim().parse())
+            .ok_or(format!("Tag object {} has no target", tag_object.display()))?;
+    }
+
+    if name.len() < 4 || !name.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("Unknown ref {name}").into());
+    }
+    let mut matches = Vec::new();
+    for entry in std::fs::read_dir(commits_path)? {
+        let entry = entry?.file_name();
+        if entry.to_string_lossy().starts_with(name) {
+            matches.push(entry);
+        }
+    }
+    match &matches[..] {
+        [] => Err(format!("Unknown ref {name}").into()),
+        [commit] => hash::from_os_str(commit),
+        _ => Err(format!("Ambiguous commit hash {name}").into()),
+    }
+}
+
+/// Resolves a revision: anything `resolve_ref` takes, followed by any number of `~n` (go back `n`
+/// parents, 1 if `n` is left out) and `^` (go back 1 parent), eg, `HEAD~2` or `v1.0^^`.
+///
+/// # Return values
+/// - Err if the name before the first `~` or `^` is unknown, a suffix is malformed, or the walk
+///   runs out of parents (too far back). The messages say which.
+/// - Ok(Oid) with the commit hash otherwise.
+///
+/// * `repo`:
+/// * `spec`:
+pub fn resolve_rev(repo: &Repository, spec: &str) -> Result<Oid> {
+    let (name, mut suffix) = spec.split_at(spec.find(['~', '^']).unwrap_or(spec.len()));
+    if name.is_empty() {
+        return Err(format!("Invalid revision {spec}, it needs a ref before ~ or ^").into());
+    }
+    let mut commit = resolve_ref(repo, name)?;
+    let mut walked = 0;
+    while let Some(op) = suffix.chars().next() {
+        suffix = &suffix[1..];
+        let steps = match op {
+            '~' => {
+                let digits = suffix
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(suffix.len());
+                let (count, rest) = suffix.split_at(digits);
+                suffix = rest;
+                match count {
+                    "" => 1,
+                    count => count.parse::<usize>()?,
+                }
+            }
+            '^' => 1,
+            _ => return Err(format!("Invalid revision {spec}").into()),
+        };
+        for _ in 0..steps {
+            commit = repo.read_commit(&commit)?.parent.ok_or_else(|| {
+                format!(
+                    "Invalid revision {spec}: {name} only has {walked} ancestor(s), too far back"
+                )
+            })?;
+            walked += 1;
+        }
+    }
+
+    Ok(commit)
+}
+
+/// Every object reachable from some commits, by kind.
+///
+/// * `commits`: the commits themselves and all of their ancestors.
+/// * `trees`: their root trees and every tree below those.
+/// * `blobs`: every blob in those trees. The blobs don't have to exist.
+#[derive(Debug, Default)]
+pub struct Reachable {
+    pub commits: HashSet<Oid>,
+    pub trees: HashSet<Oid>,
+    pub blobs: HashSet<Oid>,
+}
+
+/// Finds everything reachable from `starts`, following parents, root trees and tree entries.
+/// A tree seen once isn't read again, so unchanged subtrees across history cost nothing.
+///
+/// Anything not in the result can go, eg, for a `gc`. Callers deciding what to delete should pass
+/// every ref, not only HEAD.
+///
+/// # Return values
+/// - Err if a commit or a tree along the way cannot be read.
+/// - Ok(Reachable) otherwise.
+///
+/// * `repo`:
+/// * `starts`: the commits to start from.
+pub fn reachable_objects(
+    repo: &Repository,
+    starts: impl IntoIterator<Item = Oid>,
+) -> Result<Reachable> {
+    let mut ret = Reachable::default();
+    let mut commits: Vec<Oid> = starts.into_iter().collect();
+    let mut trees = Vec::new();
+    while let Some(commit_hash) = commits.pop() {
+        if !ret.commits.insert(commit_hash) {
+            continue;
+        }
+        let commit = repo.read_commit(&commit_hash)?;
+        commits.extend(commit.parent);
+        trees.push(commit.root);
+    }
+    while let Some(tree_hash) = trees.pop() {
+        if !ret.trees.insert(tree_hash) || tree_hash == EMPTY_TREE {
+            continue;
+        }
+        for child in read_tree_at(&repo.paths().dirs_path, &tree_hash)? {
+            match child.ftype {
+                FType::Blob => {
+                    ret.blobs.insert(child.hash);
+                }
+                FType::Tree => trees.push(child.hash),
+            }
+        }
+    }
+
+    Ok(ret)
+}
+
+/// How much of an object directory is used.
+///
+/// * `count`: the number of objects.
+/// * `bytes`: their total size on disk, ie, compressed for blobs.
+/// * `unreachable`: how many of them are not in the `reachable` set given to `store_usage`.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct StoreUsage {
+    pub count: usize,
+    pub bytes: u64,
+    pub unreachable: usize,
+}
+
+/// Counts the objects in one of `.gyat/files`, `.gyat/dirs` or `.gyat/commits`.
+/// Files that aren't named after a SHA1 aren't objects, and are left out.
+///
+/// # Return values
+/// - Err if the directory cannot be read.
+/// - Ok(StoreUsage) otherwise.
+///
+/// * `objects_dir`:
+/// * `reachable`: the objects of that kind that are still in use, see `reachable_objects`.
+pub fn store_usage(objects_dir: &Path, reachable: &HashSet<Oid>) -> Result<StoreUsage> {
+    let mut ret = StoreUsage::default();
+    for entry in std::fs::read_dir(objects_dir)? {
+        let entry = entry?;
+        let Ok(object_hash) = hash::from_os_str(&entry.file_name()) else {
+            continue;
+        };
+        ret.count += 1;
+        ret.bytes += entry.metadata()?.len();
+        if !reachable.contains(&object_hash) {
+            ret.unreachable += 1;
+        }
+    }
+
+    Ok(ret)
+}
+
+/// Reading file content from a blob.
+/// `inflate_blob` on the stored blob, for small blobs, the whole content ends up in memory. See
+/// `read_blob_to` otherwise.
+pub fn read_blob(blob_hash: &Oid) -> Result<Vec<u8>> {
+    read_blob_at(&gyat_paths()?.files_path, blob_hash)
+}
 
-    Ok(CommitObject { parent, root })
+/// `read_blob`, from the blobs in `files_path`.
+///
+/// * `files_path`: `.gyat/files` of the repository to read from.
+/// * `blob_hash`:
+pub(crate) fn read_blob_at(files_path: &Path, blob_hash: &Oid) -> Result<Vec<u8>> {
+    if !object_exists(files_path, blob_hash) {
+        return Err(format!("Blob hash {} doesn't exist", blob_hash).into());
+    }
+    inflate_blob(&std::fs::read(files_path.join(blob_hash.to_string()))?)
 }
 
-/// Reading file content from a blob
-pub fn read_blob(blob_hash: &[u8; 20]) -> Result<Vec<u8>> {
+/// Streams the content of a blob into `out`, without ever holding the whole blob in memory.
+///
+/// Exactly as many bytes as the blob's header says are written. Blobs from before there was a
+/// header were padded with zeros, so for those trailing zeros are dropped instead: any run of
+/// zeros is held back until we know it's followed by something else.
+///
+/// * `blob_hash`: the blob to read.
+/// * `out`: where the decompressed content goes, eg, the file being checked out.
+pub fn read_blob_to<W: Write>(blob_hash: &Oid, out: &mut W) -> Result<()> {
     // Get the files_path
-    let AllPaths { files_path, .. } = gyat_paths()?;
-    let blob_path = files_path.join(hash::to_string(blob_hash));
-    if !blob_path.exists() {
-        return Err(format!("Blob hash {} doesn't exist", hash::to_string(blob_hash)).into());
+    read_blob_to_at(&gyat_paths()?.files_path, blob_hash, out)
+}
+
+/// `read_blob_to`, from the blobs in `files_path`.
+///
+/// * `files_path`: `.gyat/files` of the repository to read from.
+/// * `blob_hash`:
+/// * `out`:
+pub(crate) fn read_blob_to_at<W: Write>(
+    files_path: &Path,
+    blob_hash: &Oid,
+    out: &mut W,
+) -> Result<()> {
+    if !object_exists(files_path, blob_hash) {
+        return Err(format!("Blob hash {} doesn't exist", blob_hash).into());
     }
 
-    let file = File::open(blob_path)?;
+    inflate_to(
+        BufReader::new(File::open(files_path.join(blob_hash.to_string()))?),
+        out,
+    )
+}
 
+/// Streams what `inflate_blob` would return into `out`.
+///
+/// * `reader`: the stored blob, from the start.
+/// * `out`:
+fn inflate_to<R: BufRead, W: Write>(mut reader: R, out: &mut W) -> Result<()> {
+    let header = read_blob_header(&mut reader)?;
     // Using ZlibDecoder to decode the file content
-    let mut decoder = ZlibDecoder::new(file);
-    let mut content = Vec::new();
-    decoder.read_to_end(&mut content)?;
-    let last_nonzero = content
-        .iter()
-        .rposition(|b| *b != 0)
-        .unwrap_or(content.len());
-    Ok(content.into_iter().take(last_nonzero + 1).collect())
+    let mut decoder = ZlibDecoder::new(reader);
+    if let Some(len) = header {
+        if std::io::copy(&mut (&mut decoder).take(len), out)? != len {
+            return Err("The blob is shorter than its header says".into());
+        }
+        return Ok(());
+    }
+
+    let mut buf = [0u8; 8192];
+    let mut held_zeros: u64 = 0;
+    let mut wrote_any = false;
+    loop {
+        let read = match decoder.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let chunk = &buf[..read];
+        match chunk.iter().rposition(|b| *b != 0) {
+            None => held_zeros += read as u64,
+            Some(last_nonzero) => {
+                std::io::copy(&mut std::io::repeat(0).take(held_zeros), out)?;
+                out.write_all(&chunk[..=last_nonzero])?;
+                held_zeros = (read - last_nonzero - 1) as u64;
+                wrote_any = true;
+            }
+        }
+    }
+    // a blob of nothing but zeros has always been read back as is.
+    if !wrote_any {
+        std::io::copy(&mut std::io::repeat(0).take(held_zeros), out)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::gyat_paths_in;
+
+    /// Writes a tree object into the trees in `dirs_path`.
+    ///
+    /// * `dirs_path`: `.gyat/dirs` of the repository to write into.
+    /// * `children`: (type, hash, component) of each child.
+    fn write_tree_to(dirs_path: &Path, children: &[(FType, Oid, &str)]) -> Oid {
+        let content = format_tree_content(children.iter().map(|(ftype, hash, component)| {
+            FileObjectRef {
+                ftype: *ftype,
+                hash,
+                component: OsStr::new(component),
+            }
+        }));
+        let tree_hash = Oid::of(&content);
+        std::fs::write(dirs_path.join(tree_hash.to_string()), content).unwrap();
+        tree_hash
+    }
+
+    #[test]
+    fn read_blob_to_test() {
+        let (_dir, repo_root) = utils::temp_repo();
+        let files_path = &gyat_paths_in(repo_root).files_path;
+        // a blob from before the header: a run of zeros across the 8192-byte read buffer must
+        // survive, the padding must not.
+        let mut content = b"head".to_vec();
+        content.resize(10000, 0);
+        content.extend(b"tail");
+        let mut stored = content.clone();
+        stored.resize(11264, 0);
+
+        let mut encoder = blob_encoder(Vec::new(), Compression::default());
+        encoder.write_all(&stored).unwrap();
+        let blob_hash = Oid::of(&stored);
+        write_object(files_path, &blob_hash, &encoder.finish().unwrap()).unwrap();
+
+        let mut out = Vec::new();
+        read_blob_to_at(files_path, &blob_hash, &mut out).unwrap();
+        assert_eq!(out, content);
+        assert_eq!(read_blob_at(files_path, &blob_hash).unwrap(), content);
+    }
+
+    #[test]
+    fn write_blob_test() {
+        let (_dir, repo_root) = utils::temp_repo();
+        let files_path = &gyat_paths_in(repo_root).files_path;
+        let content = b"only some of the changes\n".repeat(100);
+        let write = || write_blob_at(files_path, &content, Compression::default()).unwrap();
+        let blob_hash = write();
+        assert_eq!(read_blob_at(files_path, &blob_hash).unwrap(), content);
+        // written the same way a file with that content would be.
+        assert_eq!(blob_hash, hash::digest_bytes(&content));
+        assert_eq!(write(), blob_hash);
+    }
+
+    #[test]
+    fn deflate_inflate_test() {
+        // a tiny xorshift, so the "random" contents are the same every run.
+        let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        // around the old 1024-byte padding, plus some of any length.
+        let mut lengths = vec![0, 1, 1023, 1024, 1025, 2048, 8193];
+        lengths.extend((0..20).map(|_| (next() % 5000) as usize));
+        for len in lengths {
+            // mostly zeros, so plenty of contents end with some.
+            let mut raw: Vec<u8> = (0..len)
+                .map(|_| if next() % 3 == 0 { next() as u8 } else { 0 })
+                .collect();
+            for level in [Compression::none(), Compression::best()] {
+                assert_eq!(
+                    inflate_blob(&deflate_blob(&raw, level).unwrap()).unwrap(),
+                    raw
+                );
+            }
+            raw.extend([0; 5]);
+            let blob = deflate_blob(&raw, Compression::default()).unwrap();
+            assert_eq!(inflate_blob(&blob).unwrap(), raw, "length {}", raw.len());
+        }
+
+        // from before the header, the padding goes away.
+        let mut encoder = blob_encoder(Vec::new(), Compression::default());
+        encoder.write_all(b"old\0\0\0").unwrap();
+        assert_eq!(inflate_blob(&encoder.finish().unwrap()).unwrap(), b"old");
+        assert!(inflate_blob(b"blob x\0").is_err());
+        // a header that doesn't match the content is never written.
+        assert!(encode_blob(&mut &b"short"[..], 6, Compression::default(), Vec::new()).is_err());
+    }
+
+    #[test]
+    fn blob_header_test() {
+        let (_dir, repo_root) = utils::temp_repo();
+        let files_path = &gyat_paths_in(repo_root.clone()).files_path;
+        // trailing zeros are content too, now that the length is stored.
+        let mut ends_with_nul = b"blob_header_test".to_vec();
+        ends_with_nul.extend([0; 3]);
+        for content in [Vec::new(), vec![b'x'; 1024], ends_with_nul] {
+            let path = repo_root.join("file");
+            std::fs::write(&path, &content).unwrap();
+            let mut file = File::open(&path).unwrap();
+            let blob_hash = write_file_blob(files_path, &mut file, Compression::default()).unwrap();
+            assert_eq!(blob_hash, hash::digest_bytes(&content));
+
+            let blob = std::fs::read(files_path.join(blob_hash.to_string())).unwrap();
+            assert!(blob.starts_with(format!("blob {}\0", content.len()).as_bytes()));
+            assert_eq!(
+                blob_size_at(files_path, &blob_hash).unwrap(),
+                Some(content.len() as u64)
+            );
+            assert_eq!(read_blob_at(files_path, &blob_hash).unwrap(), content);
+        }
+
+        let mut broken = b"blob 12\0".to_vec();
+        let mut encoder = blob_encoder(Vec::new(), Compression::default());
+        encoder.write_all(b"too short").unwrap();
+        broken.extend(encoder.finish().unwrap());
+        let blob_hash = hash::digest_bytes(b"blob_header_test too short");
+        write_object(files_path, &blob_hash, &broken).unwrap();
+        assert!(read_blob_at(files_path, &blob_hash).is_err());
+    }
+
+    #[test]
+    fn write_object_race_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let content = deflate_blob(&b"raced\n".repeat(10000), Compression::default()).unwrap();
+        let hash = hash::digest_bytes(b"raced");
+        std::thread::scope(|s| {
+            for _ in 0..16 {
+                s.spawn(|| {
+                    for _ in 0..20 {
+                        write_object(dir.path(), &hash, &content).unwrap();
+                    }
+                });
+            }
+        });
+
+        // no temporary file is left behind, and the object is whole.
+        let names: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(names, [OsString::from(hash.to_string())]);
+        let stored = std::fs::read(dir.path().join(hash.to_string())).unwrap();
+        assert_eq!(stored, content);
+        assert_eq!(inflate_blob(&stored).unwrap(), b"raced\n".repeat(10000));
+    }
+
+    #[test]
+    fn list_objects_test() {
+        let (_dir, repo_root) = utils::temp_repo();
+        let repo = Repository::open_in(&repo_root).unwrap();
+        let AllPaths {
+            files_path,
+            dirs_path,
+            ..
+        } = repo.paths();
+        let mut blob_hashes = Vec::new();
+        for content in ["one", "two", "three"] {
+            let blob = deflate_blob(content.as_bytes(), Compression::default()).unwrap();
+            let blob_hash = hash::digest_bytes(content.as_bytes());
+            write_object(files_path, &blob_hash, &blob).unwrap();
+            blob_hashes.push(blob_hash);
+        }
+        blob_hashes.sort();
+        let tree_hash = write_tree_to(dirs_path, &[(FType::Blob, blob_hashes[0], "listed.txt")]);
+        // neither of these is listed.
+        std::fs::write(files_path.join("not-an-object"), "").unwrap();
+        std::fs::write(files_path.join(format!("{tree_hash}.1.2.tmp")), "").unwrap();
+
+        let list = |kind| list_objects(&repo, kind).unwrap();
+        assert_eq!(list(ObjType::File(FType::Blob)), blob_hashes);
+        assert_eq!(list(ObjType::File(FType::Tree)), [tree_hash]);
+        assert!(list(ObjType::Commit).is_empty());
+    }
+
+    #[test]
+    fn exists_test() {
+        let (_dir, repo_root) = utils::temp_repo();
+        let AllPaths {
+            files_path,
+            dirs_path,
+            commits_path,
+            ..
+        } = &gyat_paths_in(repo_root);
+        let blob_hash = write_blob_at(files_path, b"exists", Compression::default()).unwrap();
+        assert!(object_exists(files_path, &blob_hash));
+        // each kind of object has its own directory.
+        assert!(!object_exists(dirs_path, &blob_hash));
+        assert!(!object_exists(commits_path, &blob_hash));
+
+        let tree_hash = write_tree_to(dirs_path, &[(FType::Blob, blob_hash, "exists.txt")]);
+        assert!(object_exists(dirs_path, &tree_hash));
+        assert!(!object_exists(files_path, &tree_hash));
+        assert!(!object_exists(commits_path, &Oid::of(b"no such commit")));
+    }
+
+    #[test]
+    fn walk_tree_test() {
+        let (_dir, repo_root) = utils::temp_repo();
+        let dirs_path = &gyat_paths_in(repo_root).dirs_path;
+        let write_tree = |children: &[(FType, Oid, &str)]| write_tree_to(dirs_path, children);
+        let walk_tree = |root| walk_tree_with(root, |tree_hash| read_tree_at(dirs_path, tree_hash));
+        let (a, b) = (Oid::of(b"a"), Oid::of(b"b"));
+        let inner = write_tree(&[(FType::Blob, b, "z.txt"), (FType::Blob, a, "y.txt")]);
+        let root = write_tree(&[
+            (FType::Blob, a, "c.txt"),
+            (FType::Tree, EMPTY_TREE, "empty"),
+            (FType::Tree, inner, "b"),
+            (FType::Blob, b, "a.txt"),
+        ]);
+
+        let walked: Vec<_> = walk_tree(&root).collect::<Result<_>>().unwrap();
+        assert_eq!(
+            walked,
+            vec![
+                (PathBuf::from("a.txt"), FType::Blob, b),
+                (PathBuf::from("b"), FType::Tree, inner),
+                (PathBuf::from("b/y.txt"), FType::Blob, a),
+                (PathBuf::from("b/z.txt"), FType::Blob, b),
+                (PathBuf::from("c.txt"), FType::Blob, a),
+                (PathBuf::from("empty"), FType::Tree, EMPTY_TREE),
+            ]
+        );
+        // the blobs come out in path order too, empty directories included.
+        let blobs: Vec<_> = collect_blobs(walk_tree(&root))
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(
+            blobs,
+            vec![
+                (PathBuf::from("a.txt"), b),
+                (PathBuf::from("b/y.txt"), a),
+                (PathBuf::from("b/z.txt"), b),
+                (PathBuf::from("c.txt"), a),
+                (PathBuf::from("empty"), EMPTY_DIR),
+            ]
+        );
+
+        // a missing tree ends the walk with an error.
+        let missing = Oid::of(b"not a tree");
+        let broken = write_tree(&[(FType::Tree, missing, "gone"), (FType::Blob, a, "z.txt")]);
+        let mut walk = walk_tree(&broken);
+        assert!(walk.next().unwrap().is_err());
+        assert!(walk.next().is_none());
+    }
+
+    #[test]
+    fn format_tree_order_test() {
+        let (a, b) = (Oid::of(b"a"), Oid::of(b"b"));
+        let children = [
+            (FType::Blob, a, "a.txt"),
+            (FType::Tree, b, "b"),
+            (FType::Blob, b, "c.txt"),
+        ];
+        let to_refs = || {
+            children.iter().map(|(ftype, hash, component)| FileObjectRef {
+                ftype: *ftype,
+                hash,
+                component: OsStr::new(component),
+            })
+        };
+        let forward = format_tree_content(to_refs());
+        let backward = format_tree_content(to_refs().rev());
+        assert_eq!(forward, backward);
+        assert!(String::from_utf8(forward).unwrap().starts_with("blob"));
+    }
+
+    #[test]
+    fn diff_trees_test() {
+        let (_dir, repo_root) = utils::temp_repo();
+        let repo = Repository::open_in(&repo_root).unwrap();
+        let write_tree = |children: &[(FType, Oid, &str)]| {
+            write_tree_to(&repo.paths().dirs_path, children)
+        };
+        // blobs don't need to exist, only trees are read.
+        let (a, b, b2, c) = (Oid::of(b"a"), Oid::of(b"b"), Oid::of(b"b2"), Oid::of(b"c"));
+        let old_sub = write_tree(&[(FType::Blob, b, "b.txt")]);
+        let old_root = write_tree(&[(FType::Blob, a, "a.txt"), (FType::Tree, old_sub, "sub")]);
+        let new_sub = write_tree(&[(FType::Blob, b2, "b.txt"), (FType::Blob, c, "c.txt")]);
+        let new_root = write_tree(&[(FType::Tree, new_sub, "sub")]);
+
+        let changes = repo.diff_trees(&old_root, &new_root).unwrap();
+        assert_eq!(
+            changes,
+            vec![
+                (ChangeType::Del, PathBuf::from("a.txt"), Some(a), None),
+                (ChangeType::Mod, PathBuf::from("sub/b.txt"), Some(b), Some(b2)),
+                (ChangeType::New, PathBuf::from("sub/c.txt"), None, Some(c)),
+            ]
+        );
+
+        assert!(repo.diff_trees(&old_root, &old_root).unwrap().is_empty());
+        let reversed = repo.diff_trees(&new_root, &old_root).unwrap();
+        assert_eq!(reversed[0].0, ChangeType::New);
+        assert_eq!(reversed[2].0, ChangeType::Del);
+    }
+
+    #[test]
+    fn reachable_objects_test() {
+        let (_dir, repo_root) = utils::temp_repo();
+        let repo = Repository::open_in(&repo_root).unwrap();
+        let write_commit = |parent: Option<Oid>, tree: Oid| {
+            repo.write_commit(&format!(
+                "Parent: {}\nTree: {}\nMessage: reachable_objects_test\nChanges:\n",
+                parent.map(|p| p.to_string()).unwrap_or_default(),
+                tree
+            ))
+            .unwrap()
+        };
+        let write_tree = |children: &[(FType, Oid, &str)]| {
+            write_tree_to(&repo.paths().dirs_path, children)
+        };
+        let (a, b) = (Oid::of(b"reachable a"), Oid::of(b"reachable b"));
+        let sub = write_tree(&[(FType::Blob, b, "b.txt")]);
+        let first_root = write_tree(&[(FType::Blob, a, "a.txt")]);
+        let second_root = write_tree(&[
+            (FType::Tree, sub, "sub"),
+            (FType::Tree, EMPTY_TREE, "empty"),
+        ]);
+        let first = write_commit(None, first_root);
+        let second = write_commit(Some(first), second_root);
+
+        let reachable = reachable_objects(&repo, [second]).unwrap();
+        assert_eq!(reachable.commits, HashSet::from([first, second]));
+        assert_eq!(
+            reachable.trees,
+            HashSet::from([first_root, second_root, sub, EMPTY_TREE])
+        );
+        assert_eq!(reachable.blobs, HashSet::from([a, b]));
+
+        let reachable = reachable_objects(&repo, [first]).unwrap();
+        assert_eq!(reachable.commits, HashSet::from([first]));
+        assert_eq!(reachable.blobs, HashSet::from([a]));
+    }
+
+    #[test]
+    fn resolve_rev_test() {
+        let (_dir, repo_root) = utils::temp_repo();
+        let repo = Repository::open_in(&repo_root).unwrap();
+        let write_commit = |parent: Option<Oid>, message: &str| {
+            repo.write_commit(&format!(
+                "Parent: {}\nTree: {}\nMessage: {message}\nChanges:\n",
+                parent.map(|p| p.to_string()).unwrap_or_default(),
+                EMPTY_TREE
+            ))
+            .unwrap()
+        };
+        let first = write_commit(None, "resolve_rev_test 1");
+        let second = write_commit(Some(first), "resolve_rev_test 2");
+        let third = write_commit(Some(second), "resolve_rev_test 3");
+
+        let rev = |suffix: &str| resolve_rev(&repo, &format!("{third}{suffix}"));
+        assert_eq!(rev("").unwrap(), third);
+        assert_eq!(rev("^").unwrap(), second);
+        assert_eq!(rev("~").unwrap(), second);
+        assert_eq!(rev("~2").unwrap(), first);
+        assert_eq!(rev("^^").unwrap(), first);
+        assert_eq!(rev("~1^").unwrap(), first);
+        assert_eq!(rev("~0").unwrap(), third);
+
+        let too_far = rev("~3").unwrap_err().to_string();
+        assert!(too_far.contains("too far back"), "{too_far}");
+        let unknown = resolve_rev(&repo, "no-such-ref~1").unwrap_err().to_string();
+        assert!(unknown.contains("Unknown ref no-such-ref"), "{unknown}");
+        assert!(rev("~x").is_err());
+        assert!(resolve_rev(&repo, "~1").is_err());
+    }
+
+    #[test]
+    fn store_usage_test() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (kept, dropped) = (Oid::of(b"kept"), Oid::of(b"dropped"));
+        std::fs::write(tmp.path().join(kept.to_string()), "12345").unwrap();
+        std::fs::write(tmp.path().join(dropped.to_string()), "123").unwrap();
+        // not an object.
+        std::fs::write(tmp.path().join("abcd"), "ignored").unwrap();
+
+        assert_eq!(
+            store_usage(tmp.path(), &HashSet::from([kept])).unwrap(),
+            StoreUsage {
+                count: 2,
+                bytes: 8,
+                unreachable: 1
+            }
+        );
+    }
 }
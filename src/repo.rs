@@ -0,0 +1,424 @@
+//! A handle on the repository a command runs against.
+
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use flate2::Compression;
+
+use crate::{
+    hash::Oid,
+    objects::{self, BlobChange, CommitObject, FType, FileObject},
+    utils::{self, AllPaths},
+    Result,
+};
+
+/// Where each kind of object is stored, see `Repository::object_store`.
+///
+/// * `blobs`: `.gyat/files`.
+/// * `trees`: `.gyat/dirs`.
+/// * `commits`: `.gyat/commits`.
+#[derive(Debug, Clone, Copy)]
+pub struct ObjectStore<'a> {
+    pub blobs: &'a Path,
+    pub trees: &'a Path,
+    pub commits: &'a Path,
+}
+
+/// The repository, plus every object already read from it.
+///
+/// Commands should make one of these and route their object reads through it, so shared subtrees
+/// (or the same blob at different paths) only get read and decompressed once. The cache lives as
+/// long as the `Repository`, ie, a single command run. Objects never change once written, so it
+/// can't go stale.
+///
+/// * `paths`: see `utils::gyat_paths`.
+/// * `cwd_base`: see `utils::cwd_base`.
+/// * `trees`: tree hash to its children.
+/// * `blobs`: blob hash to its decompressed content.
+pub struct Repository {
+    paths: AllPaths,
+    cwd_base: Option<PathBuf>,
+    trees: RefCell<HashMap<Oid, Rc<Vec<FileObject>>>>,
+    blobs: RefCell<HashMap<Oid, Rc<Vec<u8>>>>,
+}
+
+impl Repository {
+    /// Opens the repository the current directory is in.
+    ///
+    /// # Return values
+    /// - Err if the current directory is not in a gyat repository.
+    pub fn open() -> Result<Self> {
+        Ok(Self::with_paths(utils::gyat_paths()?))
+    }
+
+    /// Opens the repository at `repo_root`, wherever the current directory is.
+    ///
+    /// # Return values
+    /// - Err if `repo_root` doesn't have a `.gyat` directory right inside it.
+    ///
+    /// * `repo_root`:
+    pub fn open_in(repo_root: &Path) -> Result<Self> {
+        if !repo_root.join(".gyat").is_dir() {
+            return Err(format!("{} is not a gyat repository root", repo_root.display()).into());
+        }
+        Ok(Self::with_paths(utils::gyat_paths_in(
+            repo_root.canonicalize()?,
+        )))
+    }
+
+    fn with_paths(paths: AllPaths) -> Self {
+        Self {
+            cwd_base: utils::cwd_base(&paths.repo_root),
+            paths,
+            trees: RefCell::new(HashMap::new()),
+            blobs: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn paths(&self) -> &AllPaths {
+        &self.paths
+    }
+
+    pub fn object_store(&self) -> ObjectStore<'_> {
+        ObjectStore {
+            blobs: &self.paths.files_path,
+            trees: &self.paths.dirs_path,
+            commits: &self.paths.commits_path,
+        }
+    }
+
+    /// `utils::display_path`, from where the current directory was when the repository was
+    /// opened.
+    ///
+    /// * `repo_relative`:
+    pub fn display_path(&self, repo_relative: &Path) -> PathBuf {
+        utils::display_path(repo_relative, self.cwd_base.as_deref())
+    }
+
+    /// Opens a file of the working tree.
+    ///
+    /// * `path_in_repo`: relative to the repository root.
+    pub fn open_file(&self, path_in_repo: &Path) -> Result<File> {
+        Ok(File::open(self.paths.repo_root.join(path_in_repo))?)
+    }
+
+    /// Stores a file of the working tree as a blob, see `objects::write_file_blob`.
+    ///
+    /// # Return values
+    /// - Err for any I/O error, eg, the file doesn't exist.
+    /// - Ok(Oid) with the blob hash otherwise. It's hashed again here, so it's the hash of what
+    ///   got stored even if the file changed in the meantime.
+    ///
+    /// * `path_in_repo`: relative to the repository root.
+    /// * `level`: the compression level, see `config::Config`.
+    pub fn write_file_blob(&self, path_in_repo: &Path, level: Compression) -> Result<Oid> {
+        objects::write_file_blob(
+            &self.paths.files_path,
+            &mut self.open_file(path_in_repo)?,
+            level,
+        )
+    }
+
+    /// The commit `HEAD` points to.
+    ///
+    /// # Return values
+    /// - Err if `HEAD` cannot be read, or doesn't hold a hash.
+    /// - Ok(None) if there's no commit yet.
+    /// - Ok(Some(hash)) otherwise.
+    pub fn head_commit(&self) -> Result<Option<Oid>> {
+        utils::read_head(&self.paths.gyat_path)?
+            .map(|head| head.parse())
+            .transpose()
+    }
+
+    /// The root tree of the `HEAD` commit, None if there's no commit yet.
+    pub fn root_tree(&self) -> Result<Option<Oid>> {
+        match self.head_commit()? {
+            Some(head) => Ok(Some(self.read_commit(&head)?.root)),
+            None => Ok(None),
+        }
+    }
+
+    /// `objects::read_commit_content`.
+    ///
+    /// * `commit_hash`:
+    pub fn read_commit(&self, commit_hash: &Oid) -> Result<CommitObject> {
+        objects::read_commit_at(&self.paths.commits_path, commit_hash)
+    }
+
+    /// Stores a commit. Moving `HEAD` to it is up to the caller, see `utils::update_head`.
+    ///
+    /// # Return values
+    /// - Err for any I/O error.
+    /// - Ok(Oid) with the commit hash otherwise.
+    ///
+    /// * `content`: the whole commit, `Parent:` line first, see `objects::read_commit_content`.
+    pub fn write_commit(&self, content: &str) -> Result<Oid> {
+        let commit_hash = Oid::of(content.as_bytes());
+        if !objects::object_exists(&self.paths.commits_path, &commit_hash) {
+            objects::write_object(&self.paths.commits_path, &commit_hash, content.as_bytes())?;
+        }
+        Ok(commit_hash)
+    }
+
+    /// `objects::read_tree_content`, read from disk at most once per tree.
+    ///
+    /// * `tree_hash`:
+    pub fn read_tree_content(&self, tree_hash: &Oid) -> Result<Rc<Vec<FileObject>>> {
+        if let Some(children) = self.trees.borrow().get(tree_hash) {
+            return Ok(Rc::clone(children));
+        }
+        let children = Rc::new(objects::read_tree_at(&self.paths.dirs_path, tree_hash)?);
+        self.trees
+            .borrow_mut()
+            .insert(*tree_hash, Rc::clone(&children));
+        Ok(children)
+    }
+
+    /// `objects::read_blob`, decompressed at most once per blob.
+    ///
+    /// * `blob_hash`:
+    pub fn read_blob(&self, blob_hash: &Oid) -> Result<Rc<Vec<u8>>> {
+        if let Some(content) = self.blobs.borrow().get(blob_hash) {
+            return Ok(Rc::clone(content));
+        }
+        let content = Rc::new(objects::read_blob_at(&self.paths.files_path, blob_hash)?);
+        self.blobs
+            .borrow_mut()
+            .insert(*blob_hash, Rc::clone(&content));
+        Ok(content)
+    }
+
+    /// `objects::read_blob_to`, served from the cache if `read_blob` already read the blob.
+    /// Otherwise it's streamed and NOT cached, this is meant for blobs too big to keep around.
+    ///
+    /// * `blob_hash`:
+    /// * `out`:
+    pub fn read_blob_to<W: Write>(&self, blob_hash: &Oid, out: &mut W) -> Result<()> {
+        if let Some(content) = self.blobs.borrow().get(blob_hash) {
+            out.write_all(content)?;
+            return Ok(());
+        }
+        objects::read_blob_to_at(&self.paths.files_path, blob_hash, out)
+    }
+
+    /// `objects::walk_tree`, with every tree read through the cache.
+    ///
+    /// * `root_hash`:
+    pub fn walk_tree(
+        &self,
+        root_hash: &Oid,
+    ) -> impl Iterator<Item = Result<(PathBuf, FType, Oid)>> + '_ {
+        objects::walk_tree_with(root_hash, |tree_hash| {
+            Ok(self.read_tree_content(tree_hash)?.to_vec())
+        })
+    }
+
+    /// `objects::get_blobs_from_root`, with every tree read through the cache.
+    ///
+    /// * `root_hash`:
+    pub fn get_blobs_from_root(&self, root_hash: &Oid) -> Result<BTreeMap<PathBuf, Oid>> {
+        objects::collect_blobs(self.walk_tree(root_hash))
+    }
+
+    /// Looks up a single path inside the tree `root_hash`, reading only the trees along the way.
+    ///
+    /// # Return values
+    /// - Err if a tree along the way cannot be read.
+    /// - Ok(None) if there's no blob at `path`, eg, it's a directory or doesn't exist.
+    /// - Ok(Some(hash)) otherwise, with the blob's hash.
+    ///
+    /// * `root_hash`:
+    /// * `path`: relative to `root_hash`.
+    pub fn find_blob(&self, root_hash: &Oid, path: &Path) -> Result<Option<Oid>> {
+        let mut current = (FType::Tree, *root_hash);
+        for component in path.components() {
+            if current.0 != FType::Tree {
+                return Ok(None);
+            }
+            let children = self.read_tree_content(&current.1)?;
+            match children
+                .iter()
+                .find(|c| c.component == component.as_os_str())
+            {
+                Some(child) => current = (child.ftype, child.hash),
+                None => return Ok(None),
+            }
+        }
+        Ok((current.0 == FType::Blob).then_some(current.1))
+    }
+
+    /// `objects::diff_trees`, with every tree read through the cache.
+    ///
+    /// * `old`:
+    /// * `new`:
+    pub fn diff_trees(&self, old: &Oid, new: &Oid) -> Result<Vec<BlobChange>> {
+        Ok(objects::diff_blobs(
+            &self.get_blobs_from_root(old)?,
+            &self.get_blobs_from_root(new)?,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::OsStr;
+
+    use super::*;
+    use crate::{fs::ChangeType, objects::FileObjectRef};
+
+    #[test]
+    fn cached_tree_test() {
+        let (_dir, repo_root) = utils::temp_repo();
+        let repo = Repository::open_in(&repo_root).unwrap();
+        let blob = Oid::of(b"cached");
+        let write_tree = |children: &[(FType, Oid, &str)]| {
+            let content =
+                objects::format_tree_content(children.iter().map(|(ftype, hash, component)| {
+                    FileObjectRef {
+                        ftype: *ftype,
+                        hash,
+                        component: OsStr::new(component),
+                    }
+                }));
+            let tree_hash = Oid::of(&content);
+            std::fs::write(repo.paths().dirs_path.join(tree_hash.to_string()), content).unwrap();
+            tree_hash
+        };
+        // the same subtree twice.
+        let shared = write_tree(&[(FType::Blob, blob, "file.txt")]);
+        let root = write_tree(&[(FType::Tree, shared, "a"), (FType::Tree, shared, "b")]);
+
+        let first = repo.read_tree_content(&shared).unwrap();
+        assert!(Rc::ptr_eq(
+            &first,
+            &repo.read_tree_content(&shared).unwrap()
+        ));
+        // the same walk as reading every tree straight from `.gyat/dirs`.
+        let uncached = || {
+            objects::walk_tree_with(&root, |tree_hash| {
+                objects::read_tree_at(&repo.paths().dirs_path, tree_hash)
+            })
+        };
+        assert_eq!(
+            repo.walk_tree(&root).collect::<Result<Vec<_>>>().unwrap(),
+            uncached().collect::<Result<Vec<_>>>().unwrap()
+        );
+        assert_eq!(
+            repo.get_blobs_from_root(&root).unwrap(),
+            objects::collect_blobs(uncached()).unwrap()
+        );
+        assert!(repo.diff_trees(&root, &root).unwrap().is_empty());
+
+        assert_eq!(
+            repo.find_blob(&root, Path::new("b/file.txt")).unwrap(),
+            Some(blob)
+        );
+        assert_eq!(repo.find_blob(&root, Path::new("b")).unwrap(), None);
+        assert_eq!(
+            repo.find_blob(&root, Path::new("c/file.txt")).unwrap(),
+            None
+        );
+        assert_eq!(
+            repo.find_blob(&root, Path::new("b/file.txt/more")).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn write_file_blob_test() {
+        let (_dir, repo_root) = utils::temp_repo();
+        let repo = Repository::open_in(&repo_root).unwrap();
+        let path = Path::new("observed.txt");
+        std::fs::write(repo_root.join(path), "as observed\n").unwrap();
+        let observed = repo.write_file_blob(path, Compression::default()).unwrap();
+        assert_eq!(*repo.read_blob(&observed).unwrap(), b"as observed\n");
+
+        // the stored blob doesn't follow the file around.
+        std::fs::write(repo_root.join(path), "edited later\n").unwrap();
+        let edited = repo.write_file_blob(path, Compression::default()).unwrap();
+        assert_ne!(edited, observed);
+        assert_eq!(*repo.read_blob(&observed).unwrap(), b"as observed\n");
+        assert!(repo
+            .write_file_blob(Path::new("missing.txt"), Compression::default())
+            .is_err());
+    }
+
+    #[test]
+    fn trailing_nul_test() {
+        let (_dir, repo_root) = utils::temp_repo();
+        let repo = Repository::open_in(&repo_root).unwrap();
+        let path = Path::new("file");
+        // commits the whole working tree, and reads `file` back from the commit.
+        let commit_and_read = |content: &[u8]| {
+            std::fs::write(repo_root.join(path), content).unwrap();
+            let mut tree = crate::dirtree::Tree::new_in(&repo_root).unwrap();
+            tree.add_path(&repo_root);
+            let commit = repo
+                .write_commit(&format!(
+                    "Parent: 0\nTree: {}\nMessage: nul\nChanges:\n",
+                    tree.to_object_file().unwrap()
+                ))
+                .unwrap();
+            let root = repo.read_commit(&commit).unwrap().root;
+            let blob = repo.find_blob(&root, path).unwrap().unwrap();
+            (blob, repo.read_blob(&blob).unwrap().to_vec())
+        };
+
+        // the stored length keeps the zeros, the name is still the padded hash it always was.
+        let (blob, content) = commit_and_read(b"x\0\0");
+        assert_eq!(content, b"x\0\0");
+        assert_eq!(blob, crate::hash::digest_bytes(b"x"));
+        // nothing but zeros.
+        let (_, content) = commit_and_read(b"\0");
+        assert_eq!(content, b"\0");
+    }
+
+    #[test]
+    fn commit_test() {
+        let (_dir, repo_root) = utils::temp_repo();
+        let repo = Repository::open_in(&repo_root).unwrap();
+        let tree = Oid::of(b"commit_test tree");
+        let content = format!(
+            "Parent: 0\nTree: {tree}\nAuthor: me <>\nMessage: repo commit\nDate: now\nChanges:\n\
+             New\tsome file\nDel\tgone\n"
+        );
+        let commit_hash = repo.write_commit(&content).unwrap();
+        assert!(objects::object_exists(
+            &repo.paths().commits_path,
+            &commit_hash
+        ));
+        assert_eq!(repo.write_commit(&content).unwrap(), commit_hash);
+
+        let commit = repo.read_commit(&commit_hash).unwrap();
+        assert_eq!(commit.parent, None);
+        assert_eq!(commit.root, tree);
+        assert_eq!(commit.message, "repo commit");
+        assert_eq!(
+            commit.changes,
+            [
+                (ChangeType::New, PathBuf::from("some file")),
+                (ChangeType::Del, PathBuf::from("gone"))
+            ]
+        );
+        // a change that isn't a token makes the commit unreadable.
+        let broken = repo
+            .write_commit(&format!("Parent: 0\nTree: {tree}\nChanges:\nNew gone\n"))
+            .unwrap();
+        assert!(repo.read_commit(&broken).is_err());
+        assert!(repo
+            .object_store()
+            .commits
+            .join(commit_hash.to_string())
+            .is_file());
+
+        let outside = tempfile::tempdir().unwrap();
+        assert!(Repository::open_in(outside.path()).is_err());
+    }
+}
@@ -1,4 +1,9 @@
-use std::path::{Path, PathBuf};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{config::Config, Result};
 
 /// Whether there's a `.gyat` directory in `path` or its parent(s).
 ///
@@ -10,14 +15,15 @@ pub fn is_repo(path: &Path) -> bool {
 /// # Returns
 /// - Some(PathBuf) if this path or one of its parents is a `gyat` repository, with value as
 ///   the path to the repository that has `.gyat` in it.
-/// - None otherwise.
+/// - None otherwise, including when `path` cannot be canonicalized, eg, it doesn't exist or is a
+///   dangling symlink.
 ///
 /// * `path`: the path to check
 pub fn get_repo_root(path: &Path) -> Option<PathBuf> {
     if path.as_os_str().is_empty() {
         return None;
     }
-    let mut path = path.canonicalize().unwrap_or_default();
+    let mut path = path.canonicalize().ok()?;
     // TOCTOU gonna scare the shit out of us, until we realize it's not relevant to our
     // project.
     // I (Huy) will need to look up to see if there's a cross-platform file-locking crate.
@@ -31,3 +37,161 @@ pub fn get_repo_root(path: &Path) -> Option<PathBuf> {
     }
     Some(path)
 }
+
+/// Resolves a path given on the command line to a path relative to the repository root, eg,
+/// `foo.txt` typed inside `repo/src` is `src/foo.txt`.
+///
+/// # Returns
+/// - Err if the path doesn't exist, or is outside the repository (which includes being inside a
+///   repository nested in it).
+/// - Ok(PathBuf) otherwise, with value as the path relative to `repo_root`. Empty for the root
+///   itself.
+///
+/// * `path`: relative to `cwd`, or absolute.
+/// * `cwd`: the directory the command runs from, usually `std::env::current_dir()`.
+/// * `repo_root`: the canonical repository root, as returned by `get_repo_root`.
+pub fn repo_relative(path: &Path, cwd: &Path, repo_root: &Path) -> Result<PathBuf> {
+    let full_path = cwd.join(path);
+    if !full_path.exists() {
+        return Err(format!("{} doesn't exist", path.display()).into());
+    }
+    let canonical = full_path.canonicalize()?;
+    if get_repo_root(&canonical).as_deref() != Some(repo_root) {
+        return Err(format!("{}: path is outside the repository", path.display()).into());
+    }
+    Ok(canonical.strip_prefix(repo_root)?.to_path_buf())
+}
+
+/// Creates an empty repository in `path`: the `.gyat` directory, everything inside it, and `path`
+/// itself if it doesn't exist yet.
+/// Nothing is printed, and nothing checks whether `path` ends up inside another repository. That's
+/// up to the caller.
+///
+/// # Returns
+/// - Err if `path` is not a directory, is already a repository, or for any I/O error.
+/// - Ok(PathBuf) otherwise, with value as the (canonical) root of the new repository.
+///
+/// * `path`: where the repository goes. Its parent must exist.
+pub fn init_repo(path: &Path) -> Result<PathBuf> {
+    if !path.exists() {
+        fs::create_dir(path)?;
+    } else if !path.is_dir() {
+        return Err(format!("{} exists but is not a directory", path.display()).into());
+    }
+
+    // Create .gyat directory
+    let gyat_path = path.join(".gyat");
+    if gyat_path.exists() {
+        return Err("This is already a .gyat repository".into());
+    }
+    fs::create_dir(&gyat_path)?;
+
+    // Create the internal structure
+    fs::create_dir(gyat_path.join("commits"))?;
+    fs::create_dir(gyat_path.join("dirs"))?;
+    fs::create_dir(gyat_path.join("files"))?;
+    fs::create_dir_all(gyat_path.join("refs").join("tags"))?;
+    fs::create_dir(gyat_path.join("tags"))?;
+    fs::write(gyat_path.join("index"), "")?;
+    // ignores that aren't tracked, like `.gyatignore` but only for this copy of the repository.
+    fs::create_dir(gyat_path.join("info"))?;
+    fs::write(gyat_path.join("info").join("exclude"), "")?;
+
+    // HEAD starts on the default branch, which has no commit yet.
+    let config = Config::default();
+    fs::create_dir_all(gyat_path.join("refs").join("heads"))?;
+    fs::write(
+        gyat_path
+            .join("refs")
+            .join("heads")
+            .join(&config.default_branch),
+        "",
+    )?;
+    fs::write(
+        gyat_path.join("HEAD"),
+        format!("ref: refs/heads/{}", config.default_branch),
+    )?;
+    config.write(&gyat_path)?;
+
+    Ok(path.canonicalize()?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn init_repo_test() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("repo");
+        let repo_root = init_repo(&path).unwrap();
+        assert_eq!(repo_root, path.canonicalize().unwrap());
+        assert_eq!(get_repo_root(&path), Some(repo_root.clone()));
+
+        let gyat_path = repo_root.join(".gyat");
+        for dir in [
+            "commits",
+            "dirs",
+            "files",
+            "tags",
+            "refs/tags",
+            "refs/heads",
+            "info",
+        ] {
+            assert!(gyat_path.join(dir).is_dir(), "{dir} is missing");
+        }
+        assert_eq!(fs::read_to_string(gyat_path.join("index")).unwrap(), "");
+        assert_eq!(
+            fs::read_to_string(gyat_path.join("HEAD")).unwrap(),
+            "ref: refs/heads/main"
+        );
+        assert!(gyat_path.join("refs/heads/main").is_file());
+        assert!(Config::load(&gyat_path).is_ok());
+
+        // twice is once too many.
+        assert!(init_repo(&path).is_err());
+    }
+
+    #[test]
+    fn repo_relative_test() {
+        let tmp = tempfile::tempdir().unwrap();
+        let repo_root = init_repo(&tmp.path().join("repo")).unwrap();
+        let src = repo_root.join("src");
+        fs::create_dir(&src).unwrap();
+        fs::write(src.join("foo.txt"), "foo").unwrap();
+        fs::write(repo_root.join("bar.txt"), "bar").unwrap();
+
+        let resolve = |path: &str| repo_relative(Path::new(path), &src, &repo_root);
+        assert_eq!(resolve("foo.txt").unwrap(), Path::new("src/foo.txt"));
+        assert_eq!(resolve(".").unwrap(), Path::new("src"));
+        assert_eq!(resolve("../bar.txt").unwrap(), Path::new("bar.txt"));
+        assert_eq!(resolve("..").unwrap(), Path::new(""));
+        assert_eq!(
+            repo_relative(&src.join("foo.txt"), &src, &repo_root).unwrap(),
+            Path::new("src/foo.txt")
+        );
+        assert!(resolve("missing.txt").is_err());
+        assert!(resolve("../..").is_err());
+
+        // a nested repository is not part of this one.
+        init_repo(&src.join("nested")).unwrap();
+        assert!(resolve("nested").is_err());
+    }
+
+    #[test]
+    fn get_repo_root_test() {
+        let tmp = tempfile::tempdir().unwrap();
+        let repo_root = init_repo(&tmp.path().join("repo")).unwrap();
+        assert_eq!(get_repo_root(&repo_root), Some(repo_root.clone()));
+        assert_eq!(get_repo_root(Path::new("")), None);
+
+        // nothing to canonicalize, even though the parent is a repository.
+        assert_eq!(get_repo_root(&repo_root.join("missing")), None);
+        #[cfg(unix)]
+        {
+            let dangling = repo_root.join("dangling");
+            std::os::unix::fs::symlink(repo_root.join("missing"), &dangling).unwrap();
+            assert_eq!(get_repo_root(&dangling), None);
+        }
+    }
+}
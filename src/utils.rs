@@ -3,7 +3,10 @@
 use crate::root;
 
 use crate::Result;
-use std::path::PathBuf;
+use chrono::Local;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
 
 /// All the useful paths we may need.
 /// Not too performant, but too nice to pass.
@@ -14,6 +17,8 @@ use std::path::PathBuf;
 /// * `commits_path`: `gyat_path.join("commits")`.
 /// * `trees_path`:
 /// * `files_path`:
+/// * `refs_path`: `gyat_path.join("refs")`. Tags live in `refs/tags`.
+/// * `tags_path`: `gyat_path.join("tags")`. Annotated tag objects.
 pub struct AllPaths {
     pub repo_root: PathBuf,
     pub gyat_path: PathBuf,
@@ -22,6 +27,8 @@ pub struct AllPaths {
     pub commits_path: PathBuf,
     pub dirs_path: PathBuf,
     pub files_path: PathBuf,
+    pub refs_path: PathBuf,
+    pub tags_path: PathBuf,
 }
 /// Convenient function to get all the paths we may need.
 /// This assumes a `gyat` repository already exists, and hence cannot be used
@@ -36,13 +43,23 @@ pub struct AllPaths {
 pub fn gyat_paths() -> Result<AllPaths> {
     let repo_root = root::get_repo_root(std::env::current_dir()?.as_path())
         .ok_or("Current directory in not in gyat repository")?;
+    Ok(gyat_paths_in(repo_root))
+}
+
+/// `gyat_paths` for the repository at `repo_root`, wherever the current directory is.
+/// Nothing is checked, the paths may not exist.
+///
+/// * `repo_root`: the directory `.gyat` is in.
+pub fn gyat_paths_in(repo_root: PathBuf) -> AllPaths {
     let gyat_path = repo_root.join(".gyat");
     let index_path = gyat_path.join("index");
     let head_path = gyat_path.join("HEAD");
     let commits_path = gyat_path.join("commits");
     let dirs_path = gyat_path.join("dirs");
     let files_path = gyat_path.join("files");
-    Ok(AllPaths {
+    let refs_path = gyat_path.join("refs");
+    let tags_path = gyat_path.join("tags");
+    AllPaths {
         repo_root,
         gyat_path,
         index_path,
@@ -50,5 +67,277 @@ pub fn gyat_paths() -> Result<AllPaths> {
         commits_path,
         dirs_path,
         files_path,
-    })
+        refs_path,
+        tags_path,
+    }
+}
+
+/// Where `HEAD` keeps its commit hash.
+///
+/// `HEAD` is either a commit hash itself (detached, or a repository made before branches), or a
+/// symbolic ref like `ref: refs/heads/main`, in which case the hash lives in that ref file.
+///
+/// # Returns
+/// - Err if `HEAD` cannot be read.
+/// - Ok(PathBuf) with the file holding the hash otherwise. It may not exist yet for a branch
+///   without commits.
+///
+/// * `gyat_path`: the `.gyat` directory.
+pub fn head_target(gyat_path: &Path) -> Result<PathBuf> {
+    let head = fs::read_to_string(gyat_path.join("HEAD"))?;
+    match head.trim().strip_prefix("ref:") {
+        Some(branch_ref) => Ok(gyat_path.join(branch_ref.trim())),
+        None => Ok(gyat_path.join("HEAD")),
+    }
+}
+
+/// Reads the commit hash `HEAD` currently points to, following the branch it is on if any.
+///
+/// # Returns
+/// - Err if `HEAD` cannot be read.
+/// - Ok(None) if there's no commit yet.
+/// - Ok(Some(String)) with the commit hash otherwise.
+///
+/// * `gyat_path`: the `.gyat` directory.
+pub fn read_head(gyat_path: &Path) -> Result<Option<String>> {
+    let target = head_target(gyat_path)?;
+    if !target.exists() {
+        return Ok(None);
+    }
+    let head = fs::read_to_string(target)?;
+    let head = head.trim();
+    if head.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(head.to_string()))
+}
+
+/// Points `HEAD` to `new`, and appends the movement to `.gyat/HEAD.log` so it can be recovered
+/// with `gyat reflog`.
+/// Anything that moves `HEAD` should go through here.
+/// If `HEAD` is on a branch, the branch is moved instead, and `HEAD` follows it.
+///
+/// Each log line looks like `<old>\t<new>\t<action>\t<date>`, where `<old>` is `0` if there was no
+/// commit before.
+///
+/// * `gyat_path`: the `.gyat` directory.
+/// * `new`: the commit hash `HEAD` should point to.
+/// * `action`: what moved `HEAD`, eg, "track" or "fallback".
+pub fn update_head(gyat_path: &Path, new: &str, action: &str) -> Result<()> {
+    let old = read_head(gyat_path)?.unwrap_or(String::from("0"));
+    let target = head_target(gyat_path)?;
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(target, new)?;
+
+    let date = Local::now().format("%a %b %d %H:%M:%S %Y");
+    let mut log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(gyat_path.join("HEAD.log"))?;
+    writeln!(log, "{old}\t{new}\t{action}\t{date}")?;
+    Ok(())
+}
+
+/// When to color the output. See `Painter`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum ColorChoice {
+    /// Only when stdout is a terminal and `NO_COLOR` isn't set.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// Wraps text in ANSI escape codes, or doesn't, depending on a `ColorChoice`.
+/// Every command that prints colors should go through one, so `--color` means the same everywhere.
+#[derive(Debug, Clone, Copy)]
+pub struct Painter {
+    enabled: bool,
+}
+
+impl Painter {
+    /// * `choice`: `ColorChoice::Auto` checks whether stdout is a terminal, and respects
+    ///   `NO_COLOR` (see <https://no-color.org>). The other two mean what they say.
+    pub fn new(choice: ColorChoice) -> Self {
+        use std::io::IsTerminal;
+        let enabled = match choice {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+                    && std::io::stdout().is_terminal()
+            }
+        };
+        Painter { enabled }
+    }
+
+    /// Additions, new files.
+    pub fn green(&self, text: &str) -> String {
+        self.paint("32", text)
+    }
+
+    /// Deletions, changes that still need attention.
+    pub fn red(&self, text: &str) -> String {
+        self.paint("31", text)
+    }
+
+    /// Headers.
+    pub fn bold(&self, text: &str) -> String {
+        self.paint("1", text)
+    }
+
+    fn paint(&self, code: &str, text: &str) -> String {
+        // nothing to see, so no point in the escape codes.
+        if self.enabled && !text.trim().is_empty() {
+            format!("\x1b[{code}m{text}\x1b[0m")
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+/// A "hashing 1234/5000 files" line on stderr that redraws itself with `\r`.
+/// Only drawn when both stdout and stderr are terminals, so piped output stays clean.
+pub struct Progress {
+    /// eg, "hashing". The unit, "files", is appended.
+    action: String,
+    done: usize,
+    total: usize,
+    enabled: bool,
+    last_draw: Option<std::time::Instant>,
+}
+
+impl Progress {
+    /// Redrawing on every file would spend more time printing than working.
+    const REDRAW_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+
+    /// * `action`: what's being done to the files, eg, "hashing".
+    /// * `total`: how many files there are.
+    pub fn new(action: &str, total: usize) -> Self {
+        use std::io::IsTerminal;
+        Progress {
+            action: action.to_string(),
+            done: 0,
+            total,
+            enabled: std::io::stdout().is_terminal() && std::io::stderr().is_terminal(),
+            last_draw: None,
+        }
+    }
+
+    /// One more file done. Redraws at most every `REDRAW_INTERVAL`.
+    pub fn tick(&mut self) {
+        self.done += 1;
+        if !self.enabled || self.last_draw.is_some_and(|t| t.elapsed() < Self::REDRAW_INTERVAL) {
+            return;
+        }
+        self.last_draw = Some(std::time::Instant::now());
+        eprint!("\r{} {}/{} files", self.action, self.done, self.total);
+    }
+
+    /// Prints the summary line, which replaces the progress line.
+    pub fn finish(self) {
+        if self.enabled && self.total > 0 {
+            eprintln!("\r{} {}/{} files, done.", self.action, self.done, self.total);
+        }
+    }
+}
+
+/// Where the current directory is, relative to the repository root. See `display_path`.
+///
+/// # Returns
+/// - None if the current directory isn't inside `repo_root`.
+/// - Some(PathBuf) otherwise, empty for the repository root itself.
+///
+/// * `repo_root`: canonical, like `gyat_paths` gives it.
+pub fn cwd_base(repo_root: &Path) -> Option<PathBuf> {
+    let cwd = std::env::current_dir().ok()?.canonicalize().ok()?;
+    Some(cwd.strip_prefix(repo_root).ok()?.to_path_buf())
+}
+
+/// Rewrites a path relative to the repository root so it's relative to the current directory
+/// instead, eg, `src/cli.rs` is shown as `cli.rs` from inside `src`. Every path shown to the user
+/// should go through here, usually as `Repository::display_path`.
+///
+/// # Returns
+/// The path relative to the current directory, `.` for the current directory itself. The path as
+/// is if the current directory isn't inside the repository.
+///
+/// * `repo_relative`: relative to the repository root, like the paths in the index.
+/// * `cwd_base`: see `cwd_base`.
+pub fn display_path(repo_relative: &Path, cwd_base: Option<&Path>) -> PathBuf {
+    match cwd_base {
+        Some(base) => relative_to(repo_relative, base),
+        None => repo_relative.to_path_buf(),
+    }
+}
+
+/// `path` as seen from `base`, both relative to the same directory.
+///
+/// * `path`:
+/// * `base`:
+fn relative_to(path: &Path, base: &Path) -> PathBuf {
+    let common = path
+        .components()
+        .zip(base.components())
+        .take_while(|(p, b)| p == b)
+        .count();
+    let mut ret = PathBuf::new();
+    for _ in base.components().skip(common) {
+        ret.push("..");
+    }
+    for component in path.components().skip(common) {
+        ret.push(component);
+    }
+    if ret.as_os_str().is_empty() {
+        ret.push(".");
+    }
+    ret
+}
+
+#[cfg(test)]
+/// A throwaway repository for tests, so they don't depend on where they run from.
+///
+/// # Returns
+/// The `TempDir` holding the repository, which deletes it once dropped (so keep it around), and
+/// the repository root.
+pub fn temp_repo() -> (tempfile::TempDir, PathBuf) {
+    let dir = tempfile::tempdir().unwrap();
+    let repo_root = root::init_repo(dir.path()).unwrap();
+    (dir, repo_root)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn painter_test() {
+        let painter = Painter::new(ColorChoice::Always);
+        assert_eq!(painter.green("a"), "\x1b[32ma\x1b[0m");
+        assert_eq!(painter.red("a"), "\x1b[31ma\x1b[0m");
+        assert_eq!(painter.bold("a"), "\x1b[1ma\x1b[0m");
+        assert_eq!(painter.red(" "), " ");
+        let painter = Painter::new(ColorChoice::Never);
+        assert_eq!(painter.green("a"), "a");
+        assert_eq!(painter.bold("a"), "a");
+    }
+
+    #[test]
+    fn relative_to_test() {
+        let rel = |path, base| relative_to(Path::new(path), Path::new(base));
+        assert_eq!(rel("src/cli.rs", ""), Path::new("src/cli.rs"));
+        assert_eq!(rel("src/cli.rs", "src"), Path::new("cli.rs"));
+        assert_eq!(rel("README.md", "src/cli"), Path::new("../../README.md"));
+        assert_eq!(rel("src/a/b.rs", "src/c"), Path::new("../a/b.rs"));
+        assert_eq!(rel("src", "src"), Path::new("."));
+        // a common prefix of the names doesn't count.
+        assert_eq!(rel("srcs/a.rs", "src"), Path::new("../srcs/a.rs"));
+
+        let shown = |base: Option<&str>| display_path(Path::new("src/cli.rs"), base.map(Path::new));
+        assert_eq!(shown(Some("src")), Path::new("cli.rs"));
+        // outside the repository, as is.
+        assert_eq!(shown(None), Path::new("src/cli.rs"));
+    }
 }